@@ -1,10 +1,10 @@
 use structopt::StructOpt;
 use super::student::StudentAct;
 
-use std::path::{Path, PathBuf};
+use std::{fs, path::{Path, PathBuf}, process::Command};
 
 use crate::{
-    asgn_spec::{AsgnSpec, SubmissionFatal},
+    asgn_spec::{AsgnSpec, RuleSubject, SubmissionFatal},
     context::Context,
     error::{ErrorLog, Error},
     util::{self, color::{FG_YELLOW, TEXT_BOLD, STYLE_RESET}},
@@ -18,7 +18,6 @@ use crate::{
     version    = "0.0.1",
     rename_all = "snake",
 )]
-
 pub struct GraderCmd {
     #[structopt(name = "base path")]
     _base_path: PathBuf, // Used only to consume the first CLI arg
@@ -64,12 +63,26 @@ pub enum GraderAct {
         asgn_name: String,
         #[structopt(name = "student name")]
         stud_name: String,
+        #[structopt(name = "version number", long = "version")]
+        version: Option<usize>,
     },
 
     #[structopt(about = "[graders only] copies the directory of all submissions of an assignment to cwd")]
     CopyAll {
         #[structopt(name = "assignment name")]
         asgn_name: String,
+        #[structopt(name = "job count", long = "jobs", default_value = "1")]
+        jobs: usize,
+    },
+
+    #[structopt(about = "[graders only] shows the logged output of the rules last run on a submission")]
+    Logs {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
+        #[structopt(name = "student name")]
+        stud_name: String,
+        #[structopt(name = "rule name", long = "rule")]
+        rule: Option<String>,
     },
 }
 
@@ -77,7 +90,7 @@ impl GraderAct {
     fn build(asgn_name: &str, context: &Context) -> Result<(), Error> {
         let spec: &AsgnSpec = context.catalog_get(asgn_name)?;
         let cwd = context.cwd.clone();
-        let _ = spec.run_ruleset(context, spec.build.as_ref(), &cwd, false);
+        let _ = spec.run_ruleset(context, spec.build.as_ref(), &cwd, false, None);
 
         Ok(())
     }
@@ -86,13 +99,13 @@ impl GraderAct {
         let spec = context.catalog_get(asgn_name)?;
         let cwd = context.cwd.clone();
 
-        let check_result = spec.run_on_grade(context, spec.check.as_ref(), &cwd, "Evaluating Checks", true);
+        let check_result = spec.run_on_grade(context, spec.check.as_ref(), &cwd, "Evaluating Checks", true, None);
 
         if check_result == Some(Err(SubmissionFatal)) {
             return Ok(());
         }
 
-        let score_result = spec.run_on_grade(context, spec.score.as_ref(), &cwd, "Evaluating Scores", true);
+        let score_result = spec.run_on_grade(context, spec.score.as_ref(), &cwd, "Evaluating Scores", true, None);
 
         if score_result == Some(Err(SubmissionFatal)) {
             return Ok(());
@@ -100,7 +113,7 @@ impl GraderAct {
 
         println!("{}", util::Hline::Bold);
         println!("{FG_YELLOW}{TEXT_BOLD}Evaluating Grades{STYLE_RESET}");
-        let _ = spec.run_ruleset(context, spec.grade.as_ref(), &cwd, true);
+        let _ = spec.run_ruleset(context, spec.grade.as_ref(), &cwd, true, None);
 
         println!("{}", util::Hline::Bold);
 
@@ -113,7 +126,7 @@ impl GraderAct {
         println!("{}", util::Hline::Bold);
         println!("{FG_YELLOW}{TEXT_BOLD}Evaluating Checks{STYLE_RESET}");
         let cwd = context.cwd.clone();
-        let _ = spec.run_ruleset(context, spec.check.as_ref(), &cwd, true);
+        let _ = spec.run_ruleset(context, spec.check.as_ref(), &cwd, true, None);
         println!("{}", util::Hline::Bold);
 
         Ok(())
@@ -125,30 +138,40 @@ impl GraderAct {
         println!("{}", util::Hline::Bold);
         println!("{FG_YELLOW}{TEXT_BOLD}Evaluating Scores{STYLE_RESET}");
         let cwd = context.cwd.clone();
-        let _ = spec.run_ruleset(context, spec.score.as_ref(), &cwd, true);
+        let _ = spec.run_ruleset(context, spec.score.as_ref(), &cwd, true, None);
         println!("{}", util::Hline::Bold);
 
         Ok(())
     }
 
-    pub fn copy(asgn_name: &str, username: &str, dst_dir: Option<&Path>, context: &Context) -> Result<(), Error> {
+    pub fn copy(
+        asgn_name: &str,
+        username: &str,
+        version: Option<usize>,
+        dst_dir: Option<&Path>,
+        context: &Context,
+    ) -> Result<(), ErrorLog> {
         let spec = context.catalog_get(asgn_name)?;
         let dst_dir = dst_dir.unwrap_or(&context.cwd);
         let dst_dir = util::make_fresh_dir(dst_dir, username);
 
-        spec.retrieve_sub(&dst_dir, username)?;
+        let slot = context.get_slot(spec, username);
+        let sub_dir = slot.version_path(version)?;
+        let log_dir = slot.reset_logs()?;
+        let subject = RuleSubject { username, slot: &slot, log_dir: Some(&log_dir) };
+        spec.retrieve_from(&sub_dir, &dst_dir)?;
 
-        let build_result = spec.run_on_submit(context, spec.build.as_ref(), &dst_dir, "Building", false);
+        let build_result = spec.run_on_submit(context, spec.build.as_ref(), &dst_dir, "Building", false, Some(&subject));
         if build_result == Some(Err(SubmissionFatal)) {
             return Ok(());
         }
 
-        let check_result = spec.run_on_submit(context, spec.check.as_ref(), &dst_dir, "Evaluating Checks", true);
+        let check_result = spec.run_on_submit(context, spec.check.as_ref(), &dst_dir, "Evaluating Checks", true, Some(&subject));
         if check_result == Some(Err(SubmissionFatal)) {
             return Ok(());
         }
 
-        let score_result = spec.run_on_submit(context, spec.score.as_ref(), &dst_dir, "Evaluating Scores", true);
+        let score_result = spec.run_on_submit(context, spec.score.as_ref(), &dst_dir, "Evaluating Scores", true, Some(&subject));
         if score_result == Some(Err(SubmissionFatal)) {
             return Ok(());
         }
@@ -157,28 +180,91 @@ impl GraderAct {
         Ok(())
     }
 
-    pub fn copy_all(asgn_name: &str, dst_dir: Option<&Path>, context: &Context) -> Result<(), Error> {
+    pub fn copy_all(asgn_name: &str, dst_dir: Option<&Path>, jobs: usize, context: &Context) -> Result<(), Error> {
         let dst_dir = dst_dir.map(Path::to_path_buf).unwrap_or(
             util::make_fresh_dir(&context.cwd, asgn_name)
         );
         util::refresh_dir(&dst_dir, 0o700, Vec::new().iter())?;
+
+        if jobs > 1 {
+            Self::copy_all_parallel(asgn_name, &dst_dir, jobs, context);
+            return Ok(());
+        }
+
         for member_name in &context.members {
             println!("{TEXT_BOLD}Retrieving Submission for '{member_name}'{STYLE_RESET}");
-            if let Err(err) = Self::copy(asgn_name, member_name, Some(&dst_dir), context) {
+            if let Err(log) = Self::copy(asgn_name, member_name, None, Some(&dst_dir), context) {
                 println!("{}", util::Hline::Bold);
-                print!("{err}");
+                for err in log {
+                    println!("{err}");
+                }
                 println!("{}", util::Hline::Bold);
             }
         }
         Ok(())
     }
 
+    fn copy_all_parallel(asgn_name: &str, dst_dir: &Path, jobs: usize, context: &Context) {
+        let commands = context.members.iter()
+            .map(|member_name| {
+                let mut cmd = Command::new(&context.exe_path);
+                cmd.arg(&context.base_path).arg("copy").arg(asgn_name).arg(member_name);
+                cmd.current_dir(dst_dir);
+                cmd
+            })
+            .collect();
+
+        util::run_buffered(commands, jobs, |idx, output, result| {
+            let member_name = &context.members[idx];
+            println!("{TEXT_BOLD}Retrieving Submission for '{member_name}'{STYLE_RESET}");
+            print!("{}", String::from_utf8_lossy(&output));
+            if let Err(err) = result {
+                println!("{}", Error::command("asgn", err));
+            }
+        });
+    }
+
+    fn logs(asgn_name: &str, username: &str, rule: Option<&str>, context: &Context) -> Result<(), Error> {
+        let spec = context.catalog_get(asgn_name)?;
+        let log_dir = context.get_slot(spec, username).log_path();
+
+        let mut logs = match log_dir.is_dir() {
+            true  => util::files_under(&log_dir, &log_dir, true)?,
+            false => Vec::new(),
+        };
+        logs.retain(|log| rule.is_none() || log.file_stem().and_then(|stem| stem.to_str()) == rule);
+
+        if logs.is_empty() {
+            return Err(Error::custom(
+                format!("No logs found for '{username}' on assignment '{asgn_name}'."),
+                "Logs are recorded whenever update_scores, copy, or copy_all run rules on a submission.",
+            ));
+        }
+
+        // Show the logs in the order the rules ran
+        logs.sort_by_key(|log| fs::metadata(log_dir.join(log)).and_then(|meta| meta.modified()).ok());
+
+        for log in logs {
+            let log_path = log_dir.join(&log);
+            let text = fs::read_to_string(&log_path).map_err(|err|
+                Error::io("Failed to read file", &log_path, err)
+            )?;
+            println!("{}", util::Hline::Bold);
+            println!("{FG_YELLOW}{TEXT_BOLD}{}{STYLE_RESET}", log.display());
+            print!("{text}");
+        }
+        println!("{}", util::Hline::Bold);
+
+        Ok(())
+    }
+
     pub fn execute(&self, context: &Context) -> Result<(), ErrorLog> {
         use GraderAct::*;
         match self {
             Student(act)                  => act.execute(context)?,
-            Copy { asgn_name, stud_name } => Self::copy(asgn_name, stud_name, None, context)?,
-            CopyAll { asgn_name }         => Self::copy_all(asgn_name, None, context)?,
+            Copy { asgn_name, stud_name, version } => Self::copy(asgn_name, stud_name, *version, None, context)?,
+            CopyAll { asgn_name, jobs }   => Self::copy_all(asgn_name, None, *jobs, context)?,
+            Logs { asgn_name, stud_name, rule } => Self::logs(asgn_name, stud_name, rule.as_deref(), context)?,
             Build { asgn_name }           => Self::build(asgn_name, context)?,
             Grade { asgn_name }           => Self::grade(asgn_name, context)?,
             Check { asgn_name }           => Self::check(asgn_name, context)?,
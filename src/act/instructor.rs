@@ -1,16 +1,20 @@
 use std::{
     str::FromStr,
     path::{Path, PathBuf},
+    process::Command,
+    collections::{HashMap, HashSet},
 };
 
 use crate:: {
     context::Context,
     error:: {ErrorLog, Error},
-    asgn_spec::{AsgnSpec, StatBlock, StatBlockSet},
+    asgn_spec::{AsgnSpec, RuleSubject, StatBlock, StatBlockSet},
     act::{student::StudentAct, grader::GraderAct},
+    receipt::{Receipt, DigestMatch},
+    table::Table,
     util::{
         self,
-        color::{FG_YELLOW, TEXT_BOLD, STYLE_RESET},
+        color::{FG_GREEN, FG_RED, FG_YELLOW, TEXT_BOLD, STYLE_RESET},
         TomlDatetimeExt,
         ChronoDateTimeExt,
     },
@@ -18,7 +22,7 @@ use crate:: {
 
 use structopt::StructOpt;
 use tempfile::tempdir_in;
-use chrono::Duration;
+use chrono::{DateTime, Duration, Local};
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -160,10 +164,28 @@ pub enum InstructorAct {
     UpdateScores {
         #[structopt(name = "assignment name")]
         asgn_name: String,
+        #[structopt(name = "job count", long = "jobs", default_value = "1")]
+        jobs: usize,
     },
 
     #[structopt(about = "[instructors only] updates published scores for all assignments based upon current submissions")]
-    UpdateAllScores {},
+    UpdateAllScores {
+        #[structopt(name = "job count", long = "jobs", default_value = "1")]
+        jobs: usize,
+    },
+
+    // Used by update_scores to score members in parallel
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    ScoreMember {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
+        #[structopt(name = "username")]
+        username: String,
+        #[structopt(name = "build root", parse(from_os_str))]
+        build_root: PathBuf,
+        #[structopt(name = "output path", parse(from_os_str))]
+        out_path: PathBuf,
+    },
 
     /*
     #[structopt(about = "[instructors only] checks an assignment specification for validity")]
@@ -208,6 +230,14 @@ pub enum InstructorAct {
         num: i64,
     },
 
+    #[structopt(about = "[instructors only] compares a submission receipt against the files currently stored for that submission")]
+    VerifyReceipt {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
+        #[structopt(name = "receipt path")]
+        receipt_path: PathBuf,
+    },
+
     #[structopt(about = "[instructors only] attempts to fix the state of the course directory")]
     Refresh {},
 }
@@ -351,8 +381,54 @@ impl InstructorAct {
         slot.set_extension(ext_days)
     }
 
+    fn verify_receipt(asgn_name: &str, receipt_path: &Path, context: &Context) -> Result<(), Error> {
+        let spec = context.catalog_get(asgn_name)?;
+        let receipt = Receipt::load(receipt_path)?;
+
+        if receipt.assignment != spec.name {
+            return Err(Error::custom(
+                format!("Receipt is for assignment '{}', not '{asgn_name}'.", receipt.assignment),
+                "Please provide a receipt for the same assignment.",
+            ));
+        }
+
+        if !context.members.contains(&receipt.username) {
+            return Err(Error::no_such_member(&receipt.username));
+        }
+
+        let slot = context.get_slot(spec, &receipt.username);
+
+        let mut table = Table::new(["FILE", "RECEIPT", "STORED", "STATUS"].map(str::to_owned));
+        let results = receipt.verify(&slot.base_path)?;
+        table.extend(results.iter().map(|check| [
+            check.digest.path.display().to_string(),
+            check.digest.sha256.clone(),
+            Table::option_repr(check.stored.as_ref()),
+            check.status.description().to_owned(),
+        ]))?;
+
+        println!("{FG_YELLOW}Receipt for '{}' at {}:{STYLE_RESET}", receipt.username, receipt.time);
+        print!("{table}");
+
+        if results.iter().all(|check| check.status == DigestMatch::Match) {
+            println!("{FG_GREEN}Receipt matches the current submission.{STYLE_RESET}");
+            return Ok(());
+        }
+
+        println!("{FG_RED}Receipt does not match the current submission.{STYLE_RESET}");
+        for version in slot.versions()? {
+            if receipt.matches(&version.path)? {
+                println!("{FG_YELLOW}Receipt matches submitted version {} from {}.{STYLE_RESET}", version.number, version.time);
+                return Ok(());
+            }
+        }
+        println!("{FG_RED}Receipt does not match any submitted version.{STYLE_RESET}");
+
+        Ok(())
+    }
+
     fn latest_score(old_stats: &StatBlockSet, username: &str, build_root: &Path, asgn: &AsgnSpec, context: &Context)
-    -> Result<Option<StatBlock>, Error>
+    -> Result<Option<StatBlock>, ErrorLog>
     {
         let slot = context.get_slot(asgn, username);
         let status = slot.status().unwrap();
@@ -361,40 +437,129 @@ impl InstructorAct {
             return Ok(None);
         };
 
-        let stats = old_stats.get_block(username);
-
-        if let Some(stats) = stats {
-            let old_time = stats.time.try_into_chrono_date_time().ok_or_else(||
-                Error::bad_stats(username, "Missing date")
-            )?;
-            if turn_in_time.signed_duration_since(old_time) <= Duration::seconds(1) {
+        let mut stat_block = match old_stats.get_block(username) {
+            Some(stats) if Self::is_up_to_date(stats, &turn_in_time)? => {
                 println!("{FG_YELLOW}{TEXT_BOLD}{username} is already up-to-date.{STYLE_RESET}");
-                return Ok(Some(stats.clone()));
+                stats.clone()
             }
-        }
+            _ => {
+                let build_path = build_root.join(username);
+                asgn.retrieve_from(&slot.base_path, &build_path)?;
+                if ! build_root.exists() {
+                    println!("{} does not exist!", build_root.display());
+                }
+                if ! build_path.exists() {
+                    println!("{} does not exist!", build_path.display());
+                }
+                let log_dir = slot.reset_logs()?;
+                let subject = RuleSubject { username, slot: &slot, log_dir: Some(&log_dir) };
+                let _ = asgn.run_ruleset(context, asgn.build.as_ref(), &build_path, false, Some(&subject));
+
+                // Scores collected before a fatal failure are kept, as are those of independent rules
+                let outcome = asgn.run_ruleset_outcome(context, asgn.score.as_ref(), &build_path, true, Some(&subject));
+
+                StatBlock {
+                    username: username.to_owned(),
+                    time: turn_in_time.to_toml_datetime(),
+                    late_penalty: None,
+                    scores: outcome.scores,
+                    adjusted_scores: None,
+                    limit_breaches: (!outcome.breaches.is_empty()).then_some(outcome.breaches),
+                    total: None,
+                    adjusted_total: None,
+                }
+            }
+        };
+
+        // Weights and maxes may change without a resubmission, as may extensions and grace
+        // days, so the total and the penalty are always recomputed.
+        stat_block.total = asgn.total(&stat_block.scores);
+        stat_block.apply_penalty(slot.late_penalty(&status)?);
+
+        Ok(Some(stat_block))
+    }
+
+    fn is_up_to_date(stats: &StatBlock, turn_in_time: &DateTime<Local>) -> Result<bool, Error> {
+        let old_time = stats.time.try_into_chrono_date_time().ok_or_else(||
+            Error::bad_stats(&stats.username, "Missing date")
+        )?;
+        Ok(turn_in_time.signed_duration_since(old_time) <= Duration::seconds(1))
+    }
 
-        let build_path = build_root.join(username);
-        asgn.retrieve_sub(&build_path, username)?;
-        if ! build_root.exists() {
-            println!("{} does not exist!", build_root.display());
+    fn member_score(old_stats: &StatBlockSet, member: &str, build_root: &Path, asgn: &AsgnSpec, context: &Context)
+    -> Option<StatBlock>
+    {
+        match Self::latest_score(old_stats, member, build_root, asgn, context) {
+            Ok(Some(block)) => Some(block),
+            Err(log) => {
+                for err in log {
+                    println!("{err}");
+                }
+                None
+            }
+            Ok(None) => {
+                println!("{FG_YELLOW}{TEXT_BOLD}{member} has no submission.{STYLE_RESET}");
+                None
+            }
         }
-        if ! build_path.exists() {
-            println!("{} does not exist!", build_path.display());
+    }
+
+    fn score_member(asgn_name: &str, username: &str, build_root: &Path, out_path: &Path, context: &Context)
+    -> Result<(), Error>
+    {
+        let spec = context.catalog_get(asgn_name)?;
+        let old_stats: StatBlockSet = util::parse_toml_file(spec.path.join(".info").join("score.toml"))?;
+
+        match Self::member_score(&old_stats, username, build_root, spec, context) {
+            Some(block) => util::write_toml_file(&block, out_path),
+            None => Ok(()),
         }
-        let _ = asgn.run_ruleset(context, asgn.build.as_ref(), &build_path, false);
+    }
 
-        let scores = asgn.run_ruleset(context, asgn.score.as_ref(), &build_path, true).unwrap_or_default();
+    fn score_members_parallel(asgn_name: &str, members: &[&String], build_root: &Path, jobs: usize, context: &Context)
+    -> Result<HashMap<String, StatBlock>, Error>
+    {
+        let out_dir = tempdir_in(build_root).map_err(|err|
+            Error::io("Failed to create temp dir", build_root, err)
+        )?;
+        let out_path = |member: &str| out_dir.path().join(format!("{member}.toml"));
+
+        let commands = members.iter()
+            .map(|member| {
+                let mut cmd = Command::new(&context.exe_path);
+                cmd.arg(&context.base_path)
+                    .arg("score_member")
+                    .arg(asgn_name)
+                    .arg(member)
+                    .arg(build_root)
+                    .arg(out_path(member));
+                cmd
+            })
+            .collect();
 
-        let stat_block = StatBlock {
-            username: username.to_owned(),
-            time: turn_in_time.to_toml_datetime(),
-            scores
-        };
+        let mut blocks = HashMap::new();
+        util::run_buffered(commands, jobs, |idx, output, result| {
+            let member = members[idx];
+            println!("{TEXT_BOLD}Scoring '{member}'{STYLE_RESET}");
+            print!("{}", String::from_utf8_lossy(&output));
+            if let Err(err) = result {
+                println!("{}", Error::command("asgn", err));
+            }
 
-        Ok(Some(stat_block))
+            let path = out_path(member);
+            if !path.exists() {
+                return;
+            }
+            match util::parse_toml_file::<StatBlock>(path) {
+                Ok(block) => { blocks.insert(member.clone(), block); }
+                Err(err) => println!("{err}"),
+            }
+        });
+
+        Ok(blocks)
     }
 
-    fn update_scores(asgn_name: &str, context: &mut Context) -> Result<(), Error> {
+    fn update_scores(asgn_name: &str, jobs: usize, context: &mut Context) -> Result<(), Error> {
         let spec = context.catalog_get(asgn_name)?;
         let info_path = spec.path.join(".info");
         let build_path = info_path.join(".internal").join("score_build");
@@ -405,24 +570,43 @@ impl InstructorAct {
         let stat_path = info_path.join("score.toml");
         let old_stats: StatBlockSet = util::parse_toml_file(&stat_path)?;
 
+        // Teammates share a submission, so their scores only need to be computed once
+        let mut seen_teams = HashSet::new();
+        let scored_members: Vec<&String> = context.members.iter()
+            .filter(|member| match spec.team_of(member) {
+                Some(team) => seen_teams.insert(team.name.clone()),
+                None => true,
+            })
+            .collect();
+
+        let mut parallel_blocks = match jobs > 1 {
+            true  => Self::score_members_parallel(asgn_name, &scored_members, build_path.path(), jobs, context)?,
+            false => HashMap::new(),
+        };
+
         let mut new_stats: StatBlockSet = Default::default();
 
         for member in &context.members {
-            match Self::latest_score(&old_stats, member, build_path.path(), spec, context) {
-                Ok(Some(block)) => if new_stats.stat_block.is_some() {
-                    new_stats.stat_block.as_mut().unwrap().push(block);
-                } else {
-                    new_stats.stat_block = Some(vec![block]);
-                }
-                Err(log) => print!("{log}"),
-                _ => println!("{FG_YELLOW}{TEXT_BOLD}{member} has no submission.{STYLE_RESET}"),
+            let block = match scored_members.contains(&member) {
+                true if jobs > 1 => parallel_blocks.remove(member),
+                true => Self::member_score(&old_stats, member, build_path.path(), spec, context),
+                false => spec.team_of(member)
+                    .and_then(|team| new_stats.get_team_block(&team.members).cloned())
+                    .map(|mut block| {
+                        block.username = member.clone();
+                        block
+                    }),
+            };
+
+            if let Some(block) = block {
+                new_stats.stat_block.get_or_insert_with(Vec::new).push(block);
             }
         }
 
         util::write_toml_file(&new_stats, stat_path)
     }
 
-    fn update_all_scores(context: &mut Context) -> Result<(), ErrorLog> {
+    fn update_all_scores(jobs: usize, context: &mut Context) -> Result<(), ErrorLog> {
         let ok_asgn: Vec<_> = context.manifest.iter()
             .filter_map(|name| context.catalog.get(name) )
             .filter_map(|asgn| asgn.as_ref().ok())
@@ -430,7 +614,7 @@ impl InstructorAct {
             .collect();
 
         let log: ErrorLog = ok_asgn.into_iter()
-            .map(|asgn| Self::update_scores(&asgn, context))
+            .map(|asgn| Self::update_scores(&asgn, jobs, context))
             .filter_map(Result::err)
             .collect();
 
@@ -462,11 +646,14 @@ impl InstructorAct {
             Unpublish       { asgn_name       } => Self::unpublish(&asgn_name, context)?,
             Enable          { asgn_name       } => Self::enable(&asgn_name, context)?,
             Disable         { asgn_name       } => Self::disable(&asgn_name, context)?,
-            UpdateScores    { asgn_name       } => Self::update_scores(&asgn_name, context)?,
-            UpdateAllScores {                 } => Self::update_all_scores(context)?,
+            UpdateScores    { asgn_name, jobs } => Self::update_scores(&asgn_name, jobs, context)?,
+            UpdateAllScores { jobs            } => Self::update_all_scores(jobs, context)?,
+            ScoreMember     { asgn_name, username, build_root, out_path } =>
+                Self::score_member(&asgn_name, &username, &build_root, &out_path, context)?,
             GraceTotal      { num             } => Self::grace_total(num, context)?,
             GraceLimit      { num             } => Self::grace_limit(num, context)?,
             Refresh         {                 } => context.refresh()?,
+            VerifyReceipt   { asgn_name, receipt_path } => Self::verify_receipt(&asgn_name, &receipt_path, context)?,
             Extend          { asgn_name, username, ext } => Self::extend(&asgn_name, &username, ext, context)?,
             SetGrace        { asgn_name, username, ext } => StudentAct::grace(&asgn_name, &username, ext, context)?,
         }
@@ -1,25 +1,7 @@
-use std::path::PathBuf;
-
 use structopt::StructOpt;
 
 use crate::{context::Context, error::Error, util::bashrc_append_line};
 
-#[derive(Debug, StructOpt)]
-#[structopt(
-    name       = "asgn",
-    author     = "Braxton Cuneo",
-    about      = "A program for managing code assignments",
-    version    = "0.0.1",
-    rename_all = "snake",
-)]
-pub struct OtherCmd {
-    #[structopt(name = "base path")]
-    _base_path: PathBuf, // Used only to consume the first CLI arg
-
-    #[structopt(subcommand)]
-    pub act: OtherAct,
-}
-
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "snake")]
 pub enum OtherAct {
@@ -2,13 +2,14 @@ use structopt::StructOpt;
 use super::other::OtherAct;
 use util::bashrc_append_line;
 
-use std::{fs, path::{Path, PathBuf}, str::FromStr, fmt};
+use std::{fs, path::{Path, PathBuf}};
 
 use crate::{
-    asgn_spec::{AsgnSpec, Ruleset, StatBlockSet, SubmissionFatal},
+    asgn_spec::{AsgnSpec, RuleSubject, Ruleset, StatBlockSet, SubmissionFatal},
+    metric::{MetricKey, MetricSpec},
     context::{Context, Role},
     error::{Error, ErrorLog, InactiveKind, FilePresenceErrorKind, CONTACT_INSTRUCTOR},
-    util::{self, color::{FG_GREEN, STYLE_RESET, FG_YELLOW}},
+    util::{self, color::{FG_GREEN, FG_RED, STYLE_RESET, FG_YELLOW}},
     table::Table,
 };
 
@@ -39,6 +40,22 @@ pub enum StudentAct {
     Submit {
         #[structopt(name = "assignment name")]
         asgn_name: String,
+        #[structopt(name = "source directory", long = "from", parse(from_os_str), conflicts_with = "archive path")]
+        from: Option<PathBuf>,
+        #[structopt(name = "archive path", long = "archive", parse(from_os_str))]
+        archive: Option<PathBuf>,
+    },
+
+    #[structopt(about = "runs the build and check rules on the files in cwd, without submitting them")]
+    Test {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
+    },
+
+    #[structopt(about = "withdraws the current submission of an assignment, so that it is no longer submitted")]
+    Withdraw {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
     },
 
     #[structopt(about = "copies setup code for assignments (if provided by the instructor)")]
@@ -51,6 +68,20 @@ pub enum StudentAct {
     Recover {
         #[structopt(name = "assignment name")]
         asgn_name: String,
+        #[structopt(name = "version number", long = "version")]
+        version: Option<usize>,
+    },
+
+    #[structopt(about = "lists the previously submitted versions of an assignment")]
+    History {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
+    },
+
+    #[structopt(about = "shows the differences between the files in cwd and the last submitted version of an assignment")]
+    Diff {
+        #[structopt(name = "assignment name")]
+        asgn_name: String,
     },
 
     #[structopt(about = "summarizes information about submissions and currently visible assignments")]
@@ -75,7 +106,7 @@ pub enum StudentAct {
         ext: i64,
     },
 
-    #[structopt(about = "lists the scores for an assignment, ordered by the given score in ascending order")]
+    #[structopt(about = "lists the scores for an assignment, ordered by the given score in ascending order (the total includes unlisted scores)")]
     RankAscending {
         #[structopt(name = "assignment name")]
         asgn_name: String,
@@ -83,7 +114,7 @@ pub enum StudentAct {
         score: String,
     },
 
-    #[structopt(about = "lists the scores for an assignment, ordered by the given score in descending order")]
+    #[structopt(about = "lists the scores for an assignment, ordered by the given score in descending order (the total includes unlisted scores)")]
     RankDescending {
         #[structopt(name = "assignment name")]
         asgn_name: String,
@@ -128,19 +159,19 @@ impl StudentAct {
         Ok(())
     }
 
-    pub fn verify_active(spec: &AsgnSpec, context: &Context) -> Result<(), InactiveKind> {
+    pub fn verify_active(spec: &AsgnSpec, context: &Context) -> Result<(), Error> {
         let is_instructor : bool = context.role == Role::Instructor;
 
         if !spec.active {
-            return Err(InactiveKind::Inactive);
+            return Err(InactiveKind::Inactive.into());
         }
 
         if !is_instructor && spec.before_open() {
-            return Err(InactiveKind::BeforeOpen);
+            return Err(InactiveKind::BeforeOpen.into());
         }
 
-        if !is_instructor && spec.after_close() {
-            return Err(InactiveKind::AfterClose);
+        if !is_instructor && context.get_slot(spec, &context.username).after_close()? {
+            return Err(InactiveKind::AfterClose.into());
         }
 
         Ok(())
@@ -173,24 +204,25 @@ impl StudentAct {
         slot.set_grace(ext_days)
     }
 
-    fn rank_specialized<T>(
+    /// The total still counts every rule, even those not shown to the caller.
+    fn rank_specialized(
         asgn: &AsgnSpec,
         ruleset: &Ruleset,
-        rule_name: &str,
+        metric: Option<(&str, &MetricSpec)>,
         up: bool,
         context: &Context,
     ) -> Result<(), Error>
-    where
-        T: FromStr + PartialOrd,
-        <T as FromStr>::Err: fmt::Display,
     {
-        let score_names : Vec<String> = ruleset.rules.iter().map(|r|r.target.clone()).collect();
+        let score_names : Vec<String> = ruleset.metrics_shown_to(&context.role).map(|(name, _)| name.to_owned()).collect();
         let mut header: Vec<String> = vec!["User".to_owned()];
         header.extend(score_names.iter().cloned());
+        if asgn.has_total() {
+            header.push(AsgnSpec::TOTAL_NAME.to_owned());
+        }
         let row_width = header.len();
         let mut table: Table = Table::new(header);
 
-        let mut rows: Vec<(Option<T>, Vec<String>)> = Vec::new();
+        let mut rows: Vec<(Option<MetricKey>, Vec<String>)> = Vec::new();
 
         let scores: StatBlockSet = util::parse_toml_file(asgn.path.join(".info").join("score.toml"))?;
 
@@ -203,21 +235,27 @@ impl StudentAct {
                 continue;
             };
 
-            let score: Option<T> = stat_block.scores.get(rule_name)
-                .map(|toml_val|
-                    T::from_str(&toml_val.to_string()).map_err(|err|
-                        Error::custom(
-                            format!("Failed to parse score {rule_name} for user {member}: {err}"),
-                            CONTACT_INSTRUCTOR,
+            let score = match metric {
+                Some((name, metric)) => stat_block.scores.get(name)
+                    .map(|toml_val|
+                        metric.key(toml_val).ok_or_else(||
+                            Error::custom(
+                                format!("Failed to parse score {name} for user {member}: expected {}", Table::option_repr(metric.kind)),
+                                CONTACT_INSTRUCTOR,
+                            )
                         )
                     )
-                )
-                .transpose()?;
+                    .transpose()?,
+                None => stat_block.final_total().map(MetricKey::Number),
+            };
 
-            row.extend(ruleset.rules.iter()
-                .map(|rule| stat_block.scores.get(&rule.target))
+            row.extend(ruleset.metrics_shown_to(&context.role)
+                .map(|(name, metric)| stat_block.scores.get(name).map(|score| metric.display(score)))
                 .map(Table::option_repr)
             );
+            if asgn.has_total() {
+                row.push(Table::option_repr(stat_block.final_total().map(|total| format!("{total:.2}"))));
+            }
 
             rows.push((score, row));
         }
@@ -225,7 +263,7 @@ impl StudentAct {
         rows.sort_by(|(a, _), (b, _)| {
             match (a, b) {
                 (Some(a_score), Some(b_score)) => {
-                    let ord = a_score.partial_cmp(b_score).unwrap();
+                    let ord = a_score.partial_cmp(b_score).unwrap_or(std::cmp::Ordering::Equal);
                     if up { ord } else { ord.reverse() }
                 }
                 (Some(_), None) => std::cmp::Ordering::Less,
@@ -251,61 +289,99 @@ impl StudentAct {
             ));
         };
 
-        let kind = ruleset.rules.iter()
-            .find(|rule| rule.target == rule_name)
-            .map(|r| r.kind.clone())
-            .ok_or(Error::custom(
+        let metric = ruleset.metrics_shown_to(&context.role).find(|(name, _)| *name == rule_name);
+
+        if metric.is_none() && !(rule_name == AsgnSpec::TOTAL_NAME && spec.has_total()) {
+            return Err(Error::custom(
                 format!("Assignment '{asgn_name}' does not have a '{rule_name}' score."),
                 "If you believe this assignment should have this score, contact the instructor."
-            ))?;
-
+            ));
+        }
 
-        let Some(kind) = kind else {
+        if metric.is_some_and(|(_, metric)| metric.kind.is_none()) {
             return Err(Error::custom("No score kind given.", "Please provide a score kind."));
-        };
-
-        match kind.as_str() {
-            "bool"  => Self::rank_specialized::<bool>(spec, ruleset, rule_name, up, context),
-            "int"   => Self::rank_specialized::<i64 >(spec, ruleset, rule_name, up, context),
-            "float" => Self::rank_specialized::<f64 >(spec, ruleset, rule_name, up, context),
-            _       => Err(Error::custom("Invalid score kind.", "Please provide a score kind."))
         }
+
+        Self::rank_specialized(spec, ruleset, metric, up, context)
     }
 
-    fn submit(asgn_name: &str, context: &Context) -> Result<(), ErrorLog> {
+    fn submit(
+        asgn_name: &str,
+        from: Option<&Path>,
+        archive: Option<&Path>,
+        context: &Context,
+    ) -> Result<(), ErrorLog> {
         let spec = context.catalog_get(asgn_name)?;
 
-        Self::verify_active(spec, context).map_err(Error::from)?;
+        Self::verify_active(spec, context)?;
 
-        let sub_dir = context.base_path.join(asgn_name).join(&context.username);
+        let slot = context.get_slot(spec, &context.username);
+        let sub_dir = slot.base_path.clone();
 
-        let src_dir = context.cwd.clone();
-        let mut log = ErrorLog::default();
-        for file_name in &spec.file_list {
-            let src_path = src_dir.join(file_name);
-            let dst_path = sub_dir.join(file_name);
+        // Kept alive until the files have been copied into the submission slot
+        let mut extract_dir = None;
 
-            if let Err(err) = FilePresenceErrorKind::assert_file(&src_path) {
-                log.push(err.at(src_path));
-                continue;
+        let src_dir = match (from, archive) {
+            (_, Some(archive)) => {
+                let temp_dir = tempfile::tempdir().map_err(|err|
+                    Error::io("Failed to create temp dir", std::env::temp_dir(), err)
+                )?;
+                let limits = spec.limits.as_ref();
+                util::extract_archive(
+                    &context.cwd.join(archive),
+                    temp_dir.path(),
+                    limits.and_then(|limits| limits.max_file_size),
+                    limits.and_then(|limits| limits.max_total_size),
+                )?;
+                extract_dir.insert(temp_dir).path().to_path_buf()
             }
+            (Some(from), None) => {
+                let from = context.cwd.join(from);
+                if !from.is_dir() {
+                    return Err(FilePresenceErrorKind::NotFound.at(from).into());
+                }
+                from
+            }
+            (None, None) => context.cwd.clone(),
+        };
 
-            fs::copy(&src_path, &dst_path).map_err(|err|
-                Error::io("Failed to copy file", src_path, err)
-            )?;
+        let files = spec.resolve_file_list(&src_dir)?;
+
+        if extract_dir.is_some() {
+            util::files_under(&src_dir, &src_dir, false)?
+                .into_iter()
+                .filter(|file| !files.contains(file))
+                .map(Error::unexpected_file)
+                .collect::<ErrorLog>()
+                .into_result::<()>()?;
+        }
+
+        spec.check_limits(&src_dir, &files)?;
+
+        slot.clear_files()?;
+        for file in &files {
+            let dst_path = util::copy_nested(&src_dir, &sub_dir, file)?;
             util::set_mode(&dst_path, 0o777)?;
         }
-        log.into_result()?;
+
+        slot.set_turn_in_time(&context.time)?;
+        let snap_path = slot.snapshot(&context.time)?;
+        let receipt = slot.write_receipt(&context.time, Some(&snap_path))?;
 
         println!("{}", util::Hline::Bold);
         println!("{FG_GREEN}Assignment '{asgn_name}' submitted!{STYLE_RESET}");
+        println!("{FG_YELLOW}Receipt for '{}' at {}:{STYLE_RESET}", receipt.username, receipt.time);
+        print!("{}", receipt.table()?);
+        println!("{FG_YELLOW}A copy of this receipt is stored at {}{STYLE_RESET}", slot.receipt_path().display());
 
+        let subject = RuleSubject { username: &context.username, slot: &slot, log_dir: None };
         let build_result = spec.run_on_submit(
             context,
             spec.build.as_ref(),
             &sub_dir,
             "Building",
             false,
+            Some(&subject),
         );
         if build_result == Some(Err(SubmissionFatal)) {
             return Ok(());
@@ -317,6 +393,7 @@ impl StudentAct {
             &sub_dir,
             "Evaluating Checks",
             false,
+            Some(&subject),
         );
         if check_result == Some(Err(SubmissionFatal)) {
             return Ok(());
@@ -328,6 +405,7 @@ impl StudentAct {
             &sub_dir,
             "Evaluating Scores",
             false,
+            Some(&subject),
         );
         if score_result == Some(Err(SubmissionFatal)) {
             return Ok(());
@@ -337,6 +415,60 @@ impl StudentAct {
         Ok(())
     }
 
+    fn test(asgn_name: &str, context: &Context) -> Result<(), ErrorLog> {
+        let spec = context.catalog_get(asgn_name)?;
+
+        Self::verify_active(spec, context)?;
+
+        let files = spec.resolve_file_list(&context.cwd)?;
+        spec.check_limits(&context.cwd, &files)?;
+
+        let temp_dir = tempfile::tempdir().map_err(|err|
+            Error::io("Failed to create temp dir", std::env::temp_dir(), err)
+        )?;
+        for file in &files {
+            let dst_path = util::copy_nested(&context.cwd, temp_dir.path(), file)?;
+            util::set_mode(&dst_path, 0o777)?;
+        }
+
+        println!("{}", util::Hline::Bold);
+        println!("{FG_YELLOW}Testing assignment '{asgn_name}' (nothing will be submitted){STYLE_RESET}");
+
+        // Mirrors what submit would report, so rulesets hidden from submissions stay hidden here
+        let slot = context.get_slot(spec, &context.username);
+        let subject = RuleSubject { username: &context.username, slot: &slot, log_dir: None };
+        let titled = [(spec.build.as_ref(), "Building"), (spec.check.as_ref(), "Evaluating Checks")];
+        for (ruleset, title) in titled {
+            let result = spec.run_on_submit(context, ruleset, temp_dir.path(), title, false, Some(&subject));
+            if result == Some(Err(SubmissionFatal)) {
+                return Ok(());
+            }
+        }
+
+        println!("{}", util::Hline::Bold);
+        Ok(())
+    }
+
+    fn withdraw(asgn_name: &str, context: &Context) -> Result<(), ErrorLog> {
+        let spec = context.catalog_get(asgn_name)?;
+
+        Self::verify_active(spec, context)?;
+
+        let slot = context.get_slot(spec, &context.username);
+        if slot.status()?.turn_in_time.is_none() {
+            return Err(Error::custom(
+                format!("Assignment '{asgn_name}' has not been submitted."),
+                "There is no submission to withdraw.",
+            ).into());
+        }
+
+        slot.withdraw(&context.time)?;
+
+        println!("{FG_GREEN}Submission of assignment '{asgn_name}' withdrawn.{STYLE_RESET}");
+        println!("{FG_YELLOW}Earlier versions remain available through the history and recover commands.{STYLE_RESET}");
+        Ok(())
+    }
+
     fn setup(asgn_name: &str, context: &Context) -> Result<(), Error> {
         let spec = context.catalog_get(asgn_name)?;
 
@@ -356,32 +488,104 @@ impl StudentAct {
         StudentAct::copy_dir(dst_dir, setup_dir)
     }
 
-    fn recover(asgn_name: &str, context: &Context) -> Result<(), ErrorLog> {
+    fn recover(asgn_name: &str, version: Option<usize>, context: &Context) -> Result<(), ErrorLog> {
         let spec = context.catalog_get(asgn_name)?;
 
-        Self::verify_active(spec, context).map_err(Error::from)?;
+        Self::verify_active(spec, context)?;
 
-        let sub_dir = context.base_path.join(asgn_name).join(&context.username);
+        let sub_dir = context.get_slot(spec, &context.username).version_path(version)?;
         let dst_dir = util::make_fresh_dir(&context.cwd, &format!("{asgn_name}_recovery"));
 
         fs::create_dir_all(&dst_dir).map_err(|err|
             Error::io("Failed to create dir", &dst_dir, err)
         )?;
 
-        let mut log: ErrorLog = Default::default();
-        for file_name in &spec.file_list {
-            let src_path = sub_dir.join(file_name);
-            if !src_path.exists() {
-                log.push(FilePresenceErrorKind::NotFound.at(file_name));
-                continue;
-            }
-            let dst_path = dst_dir.join(file_name);
+        spec.copy_file_list(&sub_dir, &dst_dir)?;
 
-            fs::copy(&src_path, &dst_path).map_err(|err|
-                Error::io("Failed to copy file", src_path, err)
-            )?;
+        Ok(())
+    }
+
+    fn history(asgn_name: &str, context: &Context) -> Result<(), Error> {
+        let spec = context.catalog_get(asgn_name)?;
+
+        if !spec.visible && context.role == Role::Student {
+            return Err(Error::invalid_asgn(asgn_name));
         }
-        log.into_result()
+
+        print!("{}", context.get_slot(spec, &context.username).history()?);
+        Ok(())
+    }
+
+    fn diff(asgn_name: &str, context: &Context) -> Result<(), Error> {
+        let spec = context.catalog_get(asgn_name)?;
+
+        if !spec.visible && context.role == Role::Student {
+            return Err(Error::invalid_asgn(asgn_name));
+        }
+
+        let slot = context.get_slot(spec, &context.username);
+        if slot.status()?.turn_in_time.is_none() {
+            return Err(Error::custom(
+                format!("Assignment '{asgn_name}' has not been submitted."),
+                "There is no submission to compare against.",
+            ));
+        }
+
+        let work_files = spec.resolve_available(&context.cwd)?;
+        let sub_files  = spec.resolve_available(&slot.base_path)?;
+
+        let mut files: Vec<&PathBuf> = work_files.iter().chain(&sub_files).collect();
+        files.sort();
+        files.dedup();
+
+        let mut table = Table::new(["FILE".to_owned(), "STATUS".to_owned()]);
+        let mut diffs = Vec::new();
+
+        for file in files {
+            let status = match (work_files.contains(file), sub_files.contains(file)) {
+                (true, false) => "NOT SUBMITTED",
+                (false, true) => "MISSING FROM CWD",
+                _ => {
+                    let work_path = context.cwd.join(file);
+                    let sub_path  = slot.base_path.join(file);
+                    let read = |path: &Path| fs::read(path).map_err(|err|
+                        Error::io("Failed to read file", path, err)
+                    );
+                    let (work, sub) = (read(&work_path)?, read(&sub_path)?);
+
+                    if work == sub {
+                        "UNCHANGED"
+                    } else {
+                        match (String::from_utf8(sub), String::from_utf8(work)) {
+                            (Ok(sub), Ok(work)) => diffs.push((file, sub, work)),
+                            _ => println!("{FG_YELLOW}Binary file {} differs.{STYLE_RESET}", file.display()),
+                        }
+                        "CHANGED"
+                    }
+                }
+            };
+            table.extend([[file.display().to_string(), status.to_owned()]])?;
+        }
+
+        for (file, sub, work) in diffs {
+            let sub_name  = format!("submitted/{}", file.display());
+            let work_name = format!("cwd/{}", file.display());
+            let diff = similar::TextDiff::from_lines(&sub, &work);
+            let unified = diff.unified_diff().header(&sub_name, &work_name).to_string();
+
+            println!("{}", util::Hline::Normal);
+            for line in unified.lines() {
+                match line.chars().next() {
+                    Some('+') => println!("{FG_GREEN}{line}{STYLE_RESET}"),
+                    Some('-') => println!("{FG_RED}{line}{STYLE_RESET}"),
+                    _ => println!("{line}"),
+                }
+            }
+        }
+
+        println!("{}", util::Hline::Normal);
+        print!("{table}");
+        Ok(())
     }
 
     fn alias(alias_name: &str, context: &Context) -> Result<(), Error> {
@@ -417,9 +621,13 @@ To import it for this shell session, run the command:
         use StudentAct::*;
         match self {
             Other          ( act        ) => act.execute(context)?,
-            Submit         { asgn_name  } => Self::submit (asgn_name, context)?,
+            Submit         { asgn_name, from, archive } => Self::submit(asgn_name, from.as_deref(), archive.as_deref(), context)?,
+            Test           { asgn_name  } => Self::test   (asgn_name, context)?,
+            Withdraw       { asgn_name  } => Self::withdraw(asgn_name, context)?,
             Setup          { asgn_name  } => Self::setup  (asgn_name, context)?,
-            Recover        { asgn_name  } => Self::recover(asgn_name, context)?,
+            Recover        { asgn_name, version } => Self::recover(asgn_name, *version, context)?,
+            History        { asgn_name  } => Self::history(asgn_name, context)?,
+            Diff           { asgn_name  } => Self::diff(asgn_name, context)?,
             Summary        {            } => context.summary()?,
             Details        { asgn_name  } => Self::details(asgn_name, context)?,
             Grace          { asgn, ext  } => Self::grace(asgn, &context.username, *ext, context)?,
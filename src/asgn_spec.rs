@@ -1,17 +1,19 @@
 use std::{
     fs,
+    io::Write,
+    collections::{HashMap, BTreeMap},
     path::{PathBuf, Path},
     process::Stdio,
     os::unix::fs::MetadataExt,
 };
 
-use itertools::Itertools;
+use itertools::{Itertools, Either};
 use serde_derive::{Serialize, Deserialize};
 use chrono::{DateTime, Local, TimeZone, Duration};
 use users::get_user_by_uid;
 
 use crate::{
-    error::{Error, FilePresenceErrorKind, CONTACT_INSTRUCTOR},
+    error::{Error, ErrorLog, FilePresenceErrorKind, CONTACT_INSTRUCTOR},
     context::{Context, Role},
     util::{
         self,
@@ -20,17 +22,33 @@ use crate::{
         TomlDatetimeExt,
     },
     table::Table,
+    receipt::Receipt,
+    limits::{RuleLimits, LimitBreach, LimitedExit},
+    metric::{MetricKind, MetricSpec, NamedMetric, DocumentFormat},
+    sandbox::Sandbox,
+    test_cases::TestCases,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Rule {
+    #[serde(default)]
     pub target: String,
+    pub command: Option<Vec<String>>,
+    pub script: Option<PathBuf>,
+    pub tests: Option<TestCases>,
     pub fail_okay: Option<bool>,
     pub wait_text: Option<String>,
     pub pass_text: Option<String>,
     pub fail_text: Option<String>,
     pub help_text: Option<String>,
-    pub kind: Option<String>,
+    #[serde(flatten)]
+    pub metric: MetricSpec,
+    pub output: Option<DocumentFormat>,
+    pub metrics: Option<Vec<NamedMetric>>,
+    pub limits: Option<RuleLimits>,
+    pub sandbox: Option<bool>,
+    pub requires: Option<Vec<String>>,
+    pub visibility: Option<Visibility>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -38,16 +56,110 @@ pub struct Ruleset {
     pub on_grade: Option<bool>,
     pub on_submit: Option<bool>,
     pub fail_okay: Option<bool>,
+    pub limits: Option<RuleLimits>,
+    pub sandbox: Option<bool>,
+    pub visibility: Option<Visibility>,
     pub rules: Vec<Rule>,
 }
 
+impl Ruleset {
+    pub fn metrics(&self) -> impl '_ + Iterator<Item=(&str, &MetricSpec)> {
+        self.rules.iter().flat_map(Self::rule_metrics)
+    }
+
+    /// Students are only shown the metrics of fully visible rules.
+    pub fn metrics_shown_to<'a>(&'a self, role: &'a Role) -> impl 'a + Iterator<Item=(&'a str, &'a MetricSpec)> {
+        self.rules.iter()
+            .filter(move |rule| self.visibility_for(rule, role) == Visibility::Visible)
+            .flat_map(Self::rule_metrics)
+    }
+
+    fn rule_metrics(rule: &Rule) -> impl '_ + Iterator<Item=(&str, &MetricSpec)> {
+        match &rule.metrics {
+            Some(metrics) => Either::Left(metrics.iter().map(|metric| (metric.name.as_str(), &metric.spec))),
+            None => Either::Right(std::iter::once((rule.target.as_str(), &rule.metric))),
+        }
+    }
+
+    pub fn visibility_for(&self, rule: &Rule, role: &Role) -> Visibility {
+        match role {
+            Role::Instructor | Role::Grader => Visibility::Visible,
+            Role::Student | Role::Other => rule.visibility.or(self.visibility).unwrap_or_default(),
+        }
+    }
+}
+
+/// How much of a rule students are shown. Graders and instructors are always shown everything.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Visible,
+    /// Whether the rule passed, but not its name, text, or output
+    ResultOnly,
+    /// Nothing at all, though the rule is still run and scored
+    Hidden,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RuleOutcome {
+    Passed, Failed, Breached(LimitBreach),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RulesetOutcome {
+    pub scores: toml::value::Table,
+    pub breaches: toml::value::Table,
+    /// Whether a rule failed in a way that stops any later rulesets
+    pub fatal: bool,
+}
+
+
+
+pub struct RuleSubject<'a> {
+    pub username: &'a str,
+    pub slot: &'a SubmissionSlot<'a>,
+    pub log_dir: Option<&'a Path>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SubmissionLimits {
+    pub max_file_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    pub text_only: Option<bool>,
+    /// An empty extension (`""`) allows files with none, such as `Makefile`
+    pub allowed_extensions: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Team {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct TeamFileToml {
+    team: Vec<Team>,
+}
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LatePolicy {
+    pub percent_per_day: Option<f64>,
+    pub percent_per_hour: Option<f64>,
+    pub max_percent: Option<f64>,
+    pub zero_after_days: Option<f64>,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct StatBlock {
     pub username: String,
     pub time: toml::value::Datetime,
+    pub late_penalty: Option<f64>,
     pub scores: toml::value::Table,
+    pub adjusted_scores: Option<toml::value::Table>,
+    pub limit_breaches: Option<toml::value::Table>,
+    pub total: Option<f64>,
+    pub adjusted_total: Option<f64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -64,6 +176,12 @@ pub struct AsgnSpecToml {
     active: bool,
     visible: bool,
     file_list: Vec<PathBuf>,
+    optional_file_list: Option<Vec<PathBuf>>,
+    limits: Option<SubmissionLimits>,
+    late_policy: Option<LatePolicy>,
+    teams: Option<Vec<Team>>,
+    team_file: Option<PathBuf>,
+    environment: Option<BTreeMap<String, String>>,
 
     build: Option<Ruleset>,
     grade: Option<Ruleset>,
@@ -82,6 +200,12 @@ impl Default for AsgnSpecToml {
             active: false,
             visible: false,
             file_list: Vec::new(),
+            optional_file_list: None,
+            limits: None,
+            late_policy: None,
+            teams: None,
+            team_file: None,
+            environment: None,
 
             build: None,
             check: None,
@@ -112,6 +236,12 @@ impl From<AsgnSpec> for AsgnSpecToml {
             active: spec.active,
             visible: spec.visible,
             file_list: spec.file_list.clone(),
+            optional_file_list: (!spec.optional_file_list.is_empty()).then_some(spec.optional_file_list),
+            limits: spec.limits,
+            late_policy: spec.late_policy,
+            teams: spec.teams,
+            team_file: spec.team_file,
+            environment: spec.environment,
 
             build: spec.build,
             check: spec.check,
@@ -138,6 +268,13 @@ pub struct AsgnSpec {
     pub active: bool,
     pub visible: bool,
     pub file_list: Vec<PathBuf>,
+    pub optional_file_list: Vec<PathBuf>,
+    pub limits: Option<SubmissionLimits>,
+    pub late_policy: Option<LatePolicy>,
+    pub teams: Option<Vec<Team>>,
+    pub team_file: Option<PathBuf>,
+    pub file_teams: Option<Vec<Team>>,
+    pub environment: Option<BTreeMap<String, String>>,
 
     pub build: Option<Ruleset>,
     pub grade: Option<Ruleset>,
@@ -150,6 +287,17 @@ pub struct AsgnSpec {
 }
 
 impl AsgnSpec {
+    pub const TOTAL_NAME: &'static str = "total";
+
+    const ENV_PREFIX: &'static str = "ASGN_";
+
+    /// Replaces the caller's environment, so that rules behave the same whoever runs them.
+    const BASE_ENVIRONMENT: [(&'static str, &'static str); 3] = [
+        ("PATH",   "/usr/local/bin:/usr/bin:/bin"),
+        ("LANG",   "C"),
+        ("LC_ALL", "C"),
+    ];
+
     pub fn from_toml(path: PathBuf, toml: AsgnSpecToml) -> Result<Self, Error> {
         let open_date = toml.open_date.map(|toml_date|
             toml_date.try_into_chrono_date_time().ok_or_else(||
@@ -169,17 +317,104 @@ impl AsgnSpec {
             )
         ).transpose()?;
 
+        let optional_file_list = toml.optional_file_list.unwrap_or_default();
+
+        for entry in toml.file_list.iter().chain(&optional_file_list) {
+            if entry.is_absolute() || entry.components().any(|c| c == std::path::Component::ParentDir) {
+                return Err(Error::bad_spec(
+                    &path,
+                    &format!("File list entry '{}' must be a relative path within the submission.", entry.display()),
+                ));
+            }
+            if let Some(pattern) = entry.to_str().filter(|text| Self::is_pattern(text)) {
+                glob::Pattern::new(pattern).map_err(|err|
+                    Error::bad_spec(&path, &format!("File list entry '{pattern}' is not a valid pattern: {err}"))
+                )?;
+            }
+        }
+
+        let mut rulesets = [toml.build, toml.check, toml.grade, toml.score];
+        for ruleset in rulesets.iter_mut().flatten() {
+            let mut earlier_targets: Vec<&str> = Vec::new();
+            for rule in &mut ruleset.rules {
+                Self::resolve_rule_name(&path, rule)?;
+                Self::resolve_test_cases(&path, rule)?;
+
+                // Rules share their name with their metric, log and score file, so each must be
+                // named apart from the others, most of all when named after a shared program
+                if earlier_targets.contains(&rule.target.as_str()) {
+                    return Err(Error::bad_spec(&path, &format!(
+                        "More than one rule in the same ruleset is named '{}'. Give each a distinct target.",
+                        rule.target,
+                    )));
+                }
+
+                let unknown = rule.requires.iter().flatten()
+                    .find(|required| !earlier_targets.contains(&required.as_str()));
+                if let Some(required) = unknown {
+                    return Err(Error::bad_spec(&path, &format!(
+                        "Rule '{}' requires '{required}', which is not an earlier rule in the same ruleset.",
+                        rule.target,
+                    )));
+                }
+                Self::check_metric(&path, rule)?;
+                earlier_targets.push(&rule.target);
+            }
+
+            let named = ruleset.rules.iter().any(|rule| rule.metrics.is_some());
+            if let Some(name) = ruleset.metrics().map(|(name, _)| name).duplicates().next().filter(|_| named) {
+                return Err(Error::bad_spec(&path, &format!("Metric '{name}' is named more than once in the same ruleset.")));
+            }
+        }
+        let [build, check, grade, score] = rulesets;
+
+        for name in toml.environment.iter().flat_map(BTreeMap::keys) {
+            let valid = name.chars().next().is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid || name.starts_with(Self::ENV_PREFIX) {
+                return Err(Error::bad_spec(&path, &format!(
+                    "Environment variable '{name}' must be a valid name that does not start with '{}'.",
+                    Self::ENV_PREFIX,
+                )));
+            }
+        }
+
+        let file_teams = toml.team_file.as_ref().map(|team_file| {
+            let team_path = path.parent().unwrap_or(&path).join(".info").join(team_file);
+            util::parse_toml_file::<TeamFileToml>(team_path).map(|file| file.team)
+        }).transpose()?;
+
+        let mut team_members: Vec<&str> = Vec::new();
+        for team in toml.teams.iter().chain(&file_teams).flatten() {
+            if team.name.is_empty() || team.name.starts_with('.') || team.name.contains('/') {
+                return Err(Error::bad_spec(&path, &format!("Team name '{}' is not a valid directory name.", team.name)));
+            }
+            for member in &team.members {
+                if team_members.contains(&member.as_str()) {
+                    return Err(Error::bad_spec(&path, &format!("User '{member}' belongs to more than one team.")));
+                }
+                team_members.push(member);
+            }
+        }
+
         Ok(Self {
             path,
             name: toml.name,
             active: toml.active,
             visible: toml.visible,
             file_list: toml.file_list,
-
-            build: toml.build,
-            check: toml.check,
-            grade: toml.grade,
-            score: toml.score,
+            optional_file_list,
+            limits: toml.limits,
+            late_policy: toml.late_policy,
+            teams: toml.teams,
+            team_file: toml.team_file,
+            file_teams,
+            environment: toml.environment,
+
+            build,
+            check,
+            grade,
+            score,
 
             due_date,
             open_date,
@@ -210,6 +445,14 @@ impl AsgnSpec {
         )
     }
 
+    pub fn all_teams(&self) -> impl '_ + Iterator<Item=&Team> {
+        self.teams.iter().chain(&self.file_teams).flatten()
+    }
+
+    pub fn team_of(&self, username: &str) -> Option<&Team> {
+        self.all_teams().find(|team| team.members.iter().any(|member| member == username))
+    }
+
     pub fn before_open(&self) -> bool {
         self.open_date.map(|date| {
             Local::now().checked_add_days(chrono::naive::Days::new(1)).unwrap()
@@ -224,22 +467,30 @@ impl AsgnSpec {
     }
 
     pub fn details(&self, context: &Context) -> Result<Table, Error> {
-        let sub_dir = context.base_path.join(&self.name).join(&context.username);
-
-        let slot = SubmissionSlot {
-            context,
-            asgn_spec: self,
-            base_path: sub_dir,
-        };
+        let slot = context.get_slot(self, &context.username);
 
         let status = slot.status().unwrap();
+        let optional_included = match status.turn_in_time {
+            Some(_) => self.resolve_optional(&slot.base_path)?,
+            None => Vec::new(),
+        };
+        let optional_included = match optional_included.is_empty() {
+            true  => Table::NONE_REPR.to_owned(),
+            false => optional_included.iter().map(|f| f.display()).join(" "),
+        };
 
         let mut table = Table::new(["PROPERTY".to_owned(), "VALUE".to_owned()]);
         table.extend([
             ["NAME".to_owned(), self.name.clone()],
+            ["TEAM".to_owned(), Table::option_repr(self.team_of(&context.username).map(|team|
+                format!("{} ({})", team.name, team.members.join(" "))
+            ))],
             ["FILES".to_owned(), self.file_list.iter().map(|f| f.display()).join(" ")],
+            ["OPTIONAL FILES".to_owned(), self.optional_file_list.iter().map(|f| f.display()).join(" ")],
+            ["OPTIONAL INCLUDED".to_owned(), optional_included],
             ["OPEN DATE".to_owned(), Table::option_repr(self.open_date.as_ref())],
             ["CLOSE DATE".to_owned(), Table::option_repr(self.close_date.as_ref())],
+            ["EFFECTIVE CLOSE DATE".to_owned(), Table::option_repr(slot.close_date(&status)?.as_ref())],
             ["DUE DATE".to_owned(), Table::option_repr(self.due_date.as_ref())],
             ["EXTENSION".to_owned(), status.extension_days.to_string()],
             ["GRACE".to_owned(), status.grace_days.to_string()],
@@ -248,6 +499,184 @@ impl AsgnSpec {
         Ok(table)
     }
 
+    pub fn has_total(&self) -> bool {
+        self.score.iter()
+            .flat_map(Ruleset::metrics)
+            .any(|(_, metric)| metric.weight.is_some() || metric.max.is_some())
+    }
+
+    /// Scores are scaled by their metric's range and weighted, with missing ones counting as 0.
+    pub fn total(&self, scores: &toml::value::Table) -> Option<f64> {
+        if !self.has_total() {
+            return None;
+        }
+        let ruleset = self.score.as_ref()?;
+
+        let (earned, possible) = ruleset.metrics()
+            .filter(|(_, metric)| metric.is_scaled())
+            .map(|(name, metric)| {
+                let fraction = scores.get(name)
+                    .and_then(|score| metric.fraction(score))
+                    .unwrap_or_default();
+                let weight = metric.weight.unwrap_or(1.0);
+                (weight * fraction, weight)
+            })
+            .fold((0.0, 0.0), |(earned, possible), (value, weight)| (earned + value, possible + weight));
+
+        (possible > 0.0).then(|| 100.0 * earned / possible)
+    }
+
+    fn check_metric(path: &Path, rule: &Rule) -> Result<(), Error> {
+        let bad_rule = |problem: &str| Error::bad_spec(path, &format!("Rule '{}' {problem}.", rule.target));
+
+        match (rule.output, &rule.metrics) {
+            (Some(format), None) => return Err(bad_rule(&format!("writes a {format} document but declares no metrics"))),
+            (None, Some(_)) => return Err(bad_rule("declares metrics but does not say whether its output is toml or json")),
+            (Some(_), Some(_)) if rule.metric != MetricSpec::default() => return Err(bad_rule(
+                "declares named metrics, so cannot have a kind, levels, weight, min or max of its own"
+            )),
+            _ => (),
+        }
+
+        if let Some(problem) = rule.metric.problem() {
+            return Err(bad_rule(&problem));
+        }
+
+        for metric in rule.metrics.iter().flatten() {
+            if let Some(problem) = metric.spec.problem() {
+                return Err(Error::bad_spec(path, &format!("Metric '{}' of rule '{}' {problem}.", metric.name, rule.target)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn resolve_rule_name(path: &Path, rule: &mut Rule) -> Result<(), Error> {
+        let ways = [rule.command.is_some(), rule.script.is_some(), rule.tests.is_some()];
+        if ways.into_iter().filter(|&way| way).count() > 1 {
+            return Err(Error::bad_spec(
+                path,
+                &format!("Rule '{}' gives more than one of a command, a script, and test cases.", rule.target),
+            ));
+        }
+
+        let program = match (&rule.command, &rule.script, &rule.tests) {
+            (Some(command), _, _) => match command.first() {
+                Some(program) => Some(PathBuf::from(program)),
+                None => return Err(Error::bad_spec(
+                    path,
+                    &format!("Rule '{}' has an empty command.", rule.target),
+                )),
+            },
+            (None, Some(script), _) => Some(script.clone()),
+            (None, None, tests) => tests.as_ref().map(|tests| tests.dir.clone()),
+        };
+
+        if rule.target.is_empty() {
+            rule.target = program.as_ref()
+                .and_then(|program| program.file_name())
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| Error::bad_spec(path, "Rule gives no target, command, script, or test cases."))?;
+        }
+
+        Ok(())
+    }
+
+    /// Rules with test cases score the number of cases passed.
+    fn resolve_test_cases(path: &Path, rule: &mut Rule) -> Result<(), Error> {
+        let Some(tests) = &rule.tests else {
+            return Ok(());
+        };
+        let bad_rule = |problem: &str| Error::bad_spec(path, &format!("Rule '{}' {problem}.", rule.target));
+
+        if tests.tolerance.is_some_and(|tolerance| tolerance.is_nan() || tolerance < 0.0) {
+            return Err(bad_rule("must not have a negative tolerance"));
+        }
+        if rule.output.is_some() || rule.metrics.is_some() {
+            return Err(bad_rule("runs test cases, so cannot declare an output document or named metrics"));
+        }
+
+        match rule.metric.kind {
+            None | Some(MetricKind::Int) => rule.metric.kind = Some(MetricKind::Int),
+            Some(kind) => return Err(bad_rule(&format!(
+                "counts the test cases passed, so must be of kind 'int' rather than '{kind}'"
+            ))),
+        }
+
+        // Hidden cases, such as those in `.info/private`, are checked by whoever can read them,
+        // so that the assignment still loads for students
+        match tests.cases(&path.join(".info")) {
+            Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => rule.metric.default_max_unknown = true,
+            Err(err) => return Err(bad_rule(&format!("has test cases that cannot be read: {err}"))),
+            Ok(cases) if cases.is_empty() => return Err(bad_rule(&format!("has no test cases in '{}'", tests.dir.display()))),
+            Ok(cases) => rule.metric.default_max = Some(cases.len() as f64),
+        }
+
+        Ok(())
+    }
+
+    fn rule_variables(&self, context: &Context) -> [(&'static str, PathBuf); 5] {
+        [
+            ("CALLER_DIR",     context.cwd.clone()),
+            ("COURSE_PUBLIC",  context.base_path.join(".info").join("public")),
+            ("COURSE_PRIVATE", context.base_path.join(".info").join("private")),
+            ("PUBLIC",         self.path.join(".info").join("public")),
+            ("PRIVATE",        self.path.join(".info").join("private")),
+        ]
+    }
+
+    /// Besides `rule_variables`, with `HOME` set to `path`, rules are given:
+    ///
+    /// | Variable              | Value |
+    /// |-----------------------|-------|
+    /// | `ASGN_NAME`           | the assignment |
+    /// | `ASGN_ROLE`           | the caller's role |
+    /// | `ASGN_CALLER`         | the caller's username |
+    /// | `ASGN_USERNAME`       | the submission's owner * |
+    /// | `ASGN_SUBMISSION`     | the submission slot * |
+    /// | `ASGN_TURN_IN_TIME`   | the turn-in time, in RFC 3339 format * † |
+    /// | `ASGN_LATE_SECONDS`   | the seconds past the extended due date, if any * † |
+    /// | `ASGN_EXTENSION_DAYS` | the slot's extension days * |
+    /// | `ASGN_GRACE_DAYS`     | the slot's grace days * |
+    ///
+    /// \* only when run on a submission. † only once something is submitted.
+    /// The spec's `environment` comes last, and may override all but the `ASGN_` variables.
+    fn rule_environment(&self, context: &Context, path: &Path, subject: Option<&RuleSubject>) -> Vec<(String, String)> {
+        let var = |name: &str, value: String| (format!("{}{name}", Self::ENV_PREFIX), value);
+
+        let mut env: Vec<(String, String)> = Self::BASE_ENVIRONMENT.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        env.push(("HOME".to_owned(), path.display().to_string()));
+        env.extend(self.rule_variables(context).map(|(name, value)|
+            (name.to_owned(), value.display().to_string())
+        ));
+
+        env.push(var("NAME", self.name.clone()));
+        env.push(var("ROLE", context.role.name().to_owned()));
+        env.push(var("CALLER", context.username.clone()));
+
+        if let Some(subject) = subject {
+            env.push(var("USERNAME", subject.username.to_owned()));
+            env.push(var("SUBMISSION", subject.slot.base_path.display().to_string()));
+
+            if let Ok(status) = subject.slot.status() {
+                if let Some(turn_in_time) = status.turn_in_time {
+                    env.push(var("TURN_IN_TIME", turn_in_time.to_rfc3339()));
+                }
+                let due_date = subject.slot.due_date(&status).ok().flatten();
+                if let Some(late_by) = due_date.and_then(|due| status.time_past(&due)) {
+                    env.push(var("LATE_SECONDS", late_by.num_seconds().to_string()));
+                }
+                env.push(var("EXTENSION_DAYS", status.extension_days.to_string()));
+                env.push(var("GRACE_DAYS", status.grace_days.to_string()));
+            }
+        }
+
+        env.extend(self.environment.iter().flatten().map(|(name, value)| (name.clone(), value.clone())));
+        env
+    }
+
     pub fn make_command(&self, target: &str, quiet: bool, context: &Context) -> std::process::Command {
         let path = self.path.join(".info").join("Makefile");
         let mut cmd  = std::process::Command::new("make");
@@ -255,38 +684,150 @@ impl AsgnSpec {
         if quiet {
             cmd.arg("--quiet");
         }
-        cmd.arg(format!(
-            "CALLER_DIR={}",
-            context.cwd.clone().into_os_string().into_string().unwrap()
-        ));
-        cmd.arg(format!(
-            "COURSE_PUBLIC={}",
-            context.base_path.join(".info").join("public").display()
-        ));
-        cmd.arg(format!(
-            "COURSE_PRIVATE={}",
-            context.base_path.join(".info").join("private").display()
-        ));
-        cmd.arg(format!(
-            "PUBLIC={}",
-            self.path.join(".info").join("public").display()
-        ));
-        cmd.arg(format!(
-            "PRIVATE={}",
-            self.path.join(".info").join("private").display()
-        ));
+        for (name, value) in self.rule_variables(context) {
+            cmd.arg(format!("{name}={}", value.display()));
+        }
         cmd.arg(format!("--file={}", path.display()));
         cmd.arg(target);
         cmd
     }
 
-    pub fn run_rule(&self, context: &Context, rule: &Rule, path: &Path) -> Result<bool, SubmissionFatal> {
-        print!("{FG_YELLOW}{TEXT_BOLD}");
-        match &rule.wait_text {
-            Some(text) => print!("{text}"),
-            None => print!("Executing '{}'.", &rule.target),
+    pub fn rule_command(&self, rule: &Rule, quiet: bool, env: &[(String, String)], context: &Context) -> std::process::Command {
+        let mut cmd = match (&rule.command, &rule.script) {
+            (Some(command), _) => {
+                let mut cmd = std::process::Command::new(&command[0]);
+                cmd.args(&command[1..]);
+                cmd
+            }
+            (None, Some(script)) => std::process::Command::new(self.path.join(".info").join(script)),
+            (None, None) => self.make_command(&rule.target, quiet, context),
+        };
+
+        cmd.env_clear().envs(env.iter().cloned());
+        cmd
+    }
+
+    /// Keeps apart the logs of same-named rules in different rulesets.
+    fn ruleset_name(&self, ruleset: &Ruleset) -> &'static str {
+        [("build", &self.build), ("check", &self.check), ("score", &self.score), ("grade", &self.grade)]
+            .into_iter()
+            .find(|(_, candidate)| candidate.as_ref().is_some_and(|candidate| std::ptr::eq(candidate, ruleset)))
+            .map_or("other", |(name, _)| name)
+    }
+
+    fn open_rule_log(log_dir: &Path, rule: &Rule, context: &Context) -> Option<fs::File> {
+        let log_path = log_dir.join(format!("{}.log", rule.target));
+        let log = fs::create_dir_all(log_dir)
+            .and_then(|_| fs::File::create(&log_path))
+            .and_then(|mut log| {
+                writeln!(log, "# Rule '{}' run by {} at {}", rule.target, context.username, context.time.to_toml_datetime())?;
+                Ok(log)
+            });
+
+        match log {
+            Ok(log) => Some(log),
+            Err(err) => {
+                println!("{}", Error::io("Failed to write log", log_path, err));
+                None
+            }
+        }
+    }
+
+    fn announce_rule(rule: &Rule) {
+        let announcement = match (rule.visibility.unwrap_or_default(), &rule.wait_text) {
+            (Visibility::Hidden, _) => return,
+            (Visibility::ResultOnly, _) => "Executing a rule.".to_owned(),
+            (Visibility::Visible, Some(text)) => text.clone(),
+            (Visibility::Visible, None) => format!("Executing '{}'.", rule.target),
+        };
+        println!("{FG_YELLOW}{TEXT_BOLD}{announcement}{STYLE_RESET}");
+    }
+
+    /// Rules that are not fully visible only show that something failed.
+    fn rule_error(rule: &Rule, err: Error) -> SubmissionFatal {
+        match rule.visibility.unwrap_or_default() {
+            Visibility::Visible => println!("{err}"),
+            Visibility::ResultOnly | Visibility::Hidden => println!("{FG_RED}! A rule failed.{STYLE_RESET}"),
+        }
+        SubmissionFatal
+    }
+
+    fn sandbox_command(rule: &Rule, cmd: &mut std::process::Command, path: &Path, context: &Context) -> Result<(), SubmissionFatal> {
+        if rule.sandbox == Some(true) {
+            let sandbox = Sandbox::new(path, &context.base_path).map_err(|err| Self::rule_error(rule, Error::sandbox_unavailable(err)))?;
+            sandbox.apply(cmd);
+        }
+        Ok(())
+    }
+
+    fn spawn_failed(rule: &Rule, program: &str, err: std::io::Error) -> SubmissionFatal {
+        match rule.sandbox == Some(true) {
+            true  => Self::rule_error(rule, Error::sandbox_unavailable(err)),
+            false => Self::rule_error(rule, Error::command(program, err)),
+        }
+    }
+
+    fn report_rule(rule: &Rule, outcome: RuleOutcome, limits: &RuleLimits, log: Option<&mut fs::File>) {
+        if let Some(log) = log {
+            let result = match outcome {
+                RuleOutcome::Passed => "passed".to_owned(),
+                RuleOutcome::Failed => "failed".to_owned(),
+                RuleOutcome::Breached(breach) => breach.describe(limits),
+            };
+            let _ = writeln!(log, "# Rule '{}' {result}", rule.target);
+        }
+
+        match rule.visibility.unwrap_or_default() {
+            Visibility::Visible => (),
+            Visibility::ResultOnly => {
+                match outcome {
+                    RuleOutcome::Passed => println!("{FG_GREEN}! The rule passed.{STYLE_RESET}"),
+                    _ => println!("{FG_RED}! The rule failed.{STYLE_RESET}"),
+                }
+                return;
+            }
+            Visibility::Hidden => return,
         }
-        println!("{STYLE_RESET}");
+
+        match outcome {
+            RuleOutcome::Passed => {
+                print!("{FG_GREEN}! ");
+                match &rule.pass_text {
+                    Some(text) => print!("{text}"),
+                    None => print!("'{}' passed.", rule.target),
+                }
+                println!("{STYLE_RESET}");
+            }
+            RuleOutcome::Failed => {
+                print!("{FG_RED}! ");
+                match &rule.fail_text {
+                    Some(text) => print!("{text}"),
+                    _ => print!("'{}' failed.", rule.target),
+                }
+                println!("{STYLE_RESET}");
+            }
+            RuleOutcome::Breached(breach) => {
+                println!("{FG_RED}! '{}' {}.{STYLE_RESET}", rule.target, breach.describe(limits));
+            }
+        }
+
+        if outcome != RuleOutcome::Passed {
+            if let Some(help) = &rule.help_text {
+                println!("{FG_YELLOW}> {help}{STYLE_RESET}");
+            }
+        }
+    }
+
+    pub fn run_rule(
+        &self,
+        context: &Context,
+        rule: &Rule,
+        path: &Path,
+        log_dir: Option<&Path>,
+        env: &[(String, String)],
+    ) -> Result<RuleOutcome, SubmissionFatal>
+    {
+        Self::announce_rule(rule);
 
         let quiet = match context.role {
             Role::Instructor => false,
@@ -295,64 +836,166 @@ impl AsgnSpec {
             Role::Other      => true,
         };
 
-        let mut cmd = self.make_command(rule.target.as_ref(), quiet, context);
-        cmd.stdin(Stdio::inherit());
-        cmd.stdout(Stdio::inherit());
-        cmd.stderr(Stdio::inherit());
+        let shown = rule.visibility.unwrap_or_default() == Visibility::Visible;
+        let output = || if shown { Stdio::inherit() } else { Stdio::null() };
 
-        let status = util::run_at(cmd, path, false).map_err(|_| SubmissionFatal)?;
+        let mut cmd = self.rule_command(rule, quiet, env, context);
+        cmd.stdout(output());
+        cmd.stderr(output());
+        Self::sandbox_command(rule, &mut cmd, path, context)?;
 
-        if status.success() {
-            print!("{FG_GREEN}! ");
-            match &rule.pass_text {
-                Some(text) => print!("{text}"),
-                None => print!("'{}' passed.", &rule.target),
-            }
-            println!("{STYLE_RESET}");
+        let mut log = log_dir.and_then(|log_dir| Self::open_rule_log(log_dir, rule, context));
+        let log_copy = log.as_ref().and_then(|log| log.try_clone().ok());
 
-            let target = path.join(&rule.target);
-            if target.exists() {
-                let _ = util::refresh_file(target, 0o777, "");
-            }
+        let program = cmd.get_program().to_string_lossy().into_owned();
+        let limits = rule.limits.clone().unwrap_or_default();
+        let exit = limits.run_at(cmd, path, log_copy, shown)
+            .map_err(|err| Self::spawn_failed(rule, &program, err))?;
 
-            Ok(true)
-        } else {
-            print!("{FG_RED}! ");
-            match &rule.fail_text {
-                Some(text) => print!("{text}"),
-                _ => print!("'{}' failed.", &rule.target),
-            }
-            println!();
+        let outcome = match exit {
+            LimitedExit::Exited(status) if status.success() => RuleOutcome::Passed,
+            LimitedExit::Exited(_) => RuleOutcome::Failed,
+            LimitedExit::Breached(breach) => RuleOutcome::Breached(breach),
+        };
 
-            if let Some(help) = &rule.help_text {
-                println!("{FG_YELLOW}> {help}{STYLE_RESET}");
+        Self::report_rule(rule, outcome, &limits, log.as_mut());
+
+        let target = path.join(&rule.target);
+        if outcome == RuleOutcome::Passed && target.exists() {
+            let _ = util::refresh_file(target, 0o777, "");
+        }
+
+        Ok(outcome)
+    }
+
+    /// Passes only if every case does, returning the number of cases that passed.
+    pub fn run_test_cases(
+        &self,
+        context: &Context,
+        rule: &Rule,
+        tests: &TestCases,
+        path: &Path,
+        log_dir: Option<&Path>,
+        env: &[(String, String)],
+    ) -> Result<(RuleOutcome, usize), SubmissionFatal>
+    {
+        Self::announce_rule(rule);
+
+        let info_dir = self.path.join(".info");
+        let cases = tests.cases(&info_dir).map_err(|err|
+            Self::rule_error(rule, Error::io("Failed to read test cases", info_dir.join(&tests.dir), err))
+        )?;
+
+        let mut log = log_dir.and_then(|log_dir| Self::open_rule_log(log_dir, rule, context));
+        let limits = rule.limits.clone().unwrap_or_default();
+
+        let shown = rule.visibility.unwrap_or_default() == Visibility::Visible;
+        let mut passed = 0usize;
+        let mut first_breach = None;
+
+        for case in &cases {
+            let unreadable = |case_path: &Path, err| Self::rule_error(rule, Error::io("Failed to read test case", case_path, err));
+            let input = fs::File::open(&case.input).map_err(|err| unreadable(&case.input, err))?;
+            let expected = fs::read(&case.expected).map_err(|err| unreadable(&case.expected, err))?;
+
+            let mut cmd = tests.command(case);
+            cmd.env_clear().envs(env.iter().cloned());
+            cmd.stderr(if shown { Stdio::inherit() } else { Stdio::null() });
+            Self::sandbox_command(rule, &mut cmd, path, context)?;
+
+            let (exit, output) = limits.run_captured(cmd, path, input)
+                .map_err(|err| Self::spawn_failed(rule, &tests.program, err))?;
+
+            let failure = match exit {
+                LimitedExit::Exited(status) if !status.success() => Some(format!("failed with {status}")),
+                LimitedExit::Exited(_) if !tests.matches(&output, &expected) => Some("produced the wrong output".to_owned()),
+                LimitedExit::Exited(_) => None,
+                LimitedExit::Breached(breach) => {
+                    first_breach.get_or_insert(breach);
+                    Some(breach.describe(&limits))
+                }
+            };
+
+            if let Some(log) = log.as_mut() {
+                let _ = writeln!(log, "# Case '{}' {}", case.name, failure.as_deref().unwrap_or("passed"));
             }
 
-            match rule.fail_okay {
-                Some(true) => Ok(false),
-                _ => Err(SubmissionFatal),
+            if failure.is_none() {
+                passed += 1;
+            }
+            if shown {
+                match failure {
+                    Some(failure) => println!("{FG_RED}  Case '{}' {failure}.{STYLE_RESET}", case.name),
+                    None => println!("{FG_GREEN}  Case '{}' passed.{STYLE_RESET}", case.name),
+                }
             }
         }
+
+        if shown {
+            println!("{passed} of {} test cases passed.", cases.len());
+        }
+
+        // The cases are checked when the assignment is loaded, but may have been removed since
+        let outcome = match first_breach {
+            _ if passed == cases.len() && !cases.is_empty() => RuleOutcome::Passed,
+            Some(breach) => RuleOutcome::Breached(breach),
+            None => RuleOutcome::Failed,
+        };
+
+        Self::report_rule(rule, outcome, &limits, log.as_mut());
+
+        Ok((outcome, passed))
     }
 
-    fn log_metric(scores: &mut toml::value::Table, target: &str, result: &str, kind: &str) {
-        let score = match kind {
-            "bool"  => result.parse::<bool>().map(toml::Value::Boolean).ok(),
-            "int"   => result.parse::<i64 >().map(toml::Value::Integer).ok(),
-            "float" => result.parse::<f64 >().map(toml::Value::Float  ).ok(),
-            _ => {
-                println!("{FG_RED}! Metric '{target}' has invalid kind '{kind}'{STYLE_RESET}");
+    fn log_metric(scores: &mut toml::value::Table, target: &str, metric: &MetricSpec, result: &str, shown: bool) {
+        // Programs almost always end their output with a newline
+        let result = result.trim();
+
+        let score = match metric.parse(result) {
+            Ok(score) => score,
+            Err(problem) => {
+                if shown {
+                    println!("{FG_RED}Metric '{target}' had result '{result}' which {problem}{STYLE_RESET}");
+                }
                 return;
-            },
+            }
         };
 
-        let Some(score) = score else {
-            println!("{FG_RED}Metric '{target}' had result '{result}' which failed to parse into '{kind}'{STYLE_RESET}");
-            return;
+        if shown {
+            println!("{FG_YELLOW}Metric '{target}' had value '{}'{STYLE_RESET}", metric.display(&score));
+        }
+        scores.insert(target.to_string(), score);
+    }
+
+    fn log_metric_document(scores: &mut toml::value::Table, rule: &Rule, format: DocumentFormat, document: &str, shown: bool) {
+        let target = &rule.target;
+
+        let values = match format.parse_document(document) {
+            Ok(values) => values,
+            Err(err) => {
+                if shown {
+                    println!("{FG_RED}! Output of '{target}' is not a valid {format} table of metrics: {err}{STYLE_RESET}");
+                }
+                return;
+            }
         };
 
-        scores.insert(target.to_string(), score);
-        println!("{FG_YELLOW}Metric '{target}' had value '{result}'{STYLE_RESET}");
+        let metrics = rule.metrics.as_deref().unwrap_or_default();
+        for metric in metrics {
+            match values.get(&metric.name) {
+                Some(result) => Self::log_metric(scores, &metric.name, &metric.spec, result, shown),
+                None if shown => println!("{FG_RED}Metric '{}' is missing from the output of '{target}'{STYLE_RESET}", metric.name),
+                None => (),
+            }
+        }
+
+        let undeclared = values.keys()
+            .filter(|name| !metrics.iter().any(|metric| &metric.name == *name))
+            .sorted()
+            .join(", ");
+        if shown && !undeclared.is_empty() {
+            println!("{FG_YELLOW}! Output of '{target}' has undeclared metrics ({undeclared}), which were ignored.{STYLE_RESET}");
+        }
     }
 
     pub fn run_ruleset(
@@ -361,69 +1004,160 @@ impl AsgnSpec {
         ruleset: Option<&Ruleset>,
         path: &Path,
         is_metric: bool,
-    ) -> Result<toml::value::Table, SubmissionFatal>
+        subject: Option<&RuleSubject>,
+    ) -> Result<RulesetOutcome, SubmissionFatal>
+    {
+        let outcome = self.run_ruleset_outcome(context, ruleset, path, is_metric, subject);
+        match outcome.fatal {
+            true  => Err(SubmissionFatal),
+            false => Ok(outcome),
+        }
+    }
+
+    /// Like `run_ruleset`, but keeps whatever was collected despite a fatal failure.
+    pub fn run_ruleset_outcome(
+        &self,
+        context: &Context,
+        ruleset: Option<&Ruleset>,
+        path: &Path,
+        is_metric: bool,
+        subject: Option<&RuleSubject>,
+    ) -> RulesetOutcome
     {
-        let mut scores = toml::value::Table::default();
+        let mut outcome = RulesetOutcome::default();
 
         if ruleset.is_none() {
             println!("{FG_YELLOW}No targets.{STYLE_RESET}");
-            return Ok(scores)
+            return outcome
         }
 
         let ruleset = ruleset.unwrap();
+        let env = self.rule_environment(context, path, subject);
+        let log_dir = subject.and_then(|subject| subject.log_dir)
+            .map(|log_dir| log_dir.join(self.ruleset_name(ruleset)));
 
-        let count = ruleset.rules.len();
+        let visibility_of = |rule: &Rule| ruleset.visibility_for(rule, &context.role);
+
+        // Hidden rules are left out of the counts, so that their existence is not revealed,
+        // unless one fails in a way that stops the whole ruleset
+        let mut count = ruleset.rules.iter().filter(|rule| visibility_of(rule) != Visibility::Hidden).count();
         let mut passed = 0usize;
         let mut failed = 0usize;
+        let mut breached = 0usize;
+        let mut skipped = 0usize;
         let mut fatal = false;
 
+        // Once any rule declares its requirements, a failure only stops the rules that depend
+        // on it. Otherwise, rules are treated as a sequence that stops at the first failure.
+        let uses_requires = ruleset.rules.iter().any(|rule| rule.requires.is_some());
+        // Maps each failed or skipped rule to the failed rule responsible, and its visibility
+        let mut unmet: HashMap<String, (String, Visibility)> = HashMap::new();
+
         for mut rule in ruleset.rules.iter().cloned() {
             rule.fail_okay.get_or_insert(ruleset.fail_okay.unwrap_or(false));
-            println!("{}", util::Hline::Normal);
-            let mut did_pass: bool = false;
-
-            match self.run_rule(context, &rule, path) {
-                Ok(pass) => {
-                    if pass {
-                        passed += 1;
-                        did_pass = true;
-                    } else {
-                        failed += 1;
+            rule.sandbox = rule.sandbox.or(ruleset.sandbox);
+            if let Some(limits) = &ruleset.limits {
+                rule.limits = Some(rule.limits.unwrap_or_default().or(limits));
+            }
+            let visibility = visibility_of(&rule);
+            rule.visibility = Some(visibility);
+            let shown = visibility == Visibility::Visible;
+            let counted = visibility != Visibility::Hidden;
+
+            if counted {
+                println!("{}", util::Hline::Normal);
+            }
+
+            let blocker = rule.requires.iter().flatten().find_map(|required| unmet.get(required)).cloned();
+            if let Some((blocker, blocker_visibility)) = blocker {
+                match (visibility, blocker_visibility) {
+                    (Visibility::Hidden, _) => (),
+                    (Visibility::Visible, Visibility::Visible) =>
+                        println!("{FG_YELLOW}! '{}' skipped because '{blocker}' failed.{STYLE_RESET}", rule.target),
+                    _ => println!("{FG_YELLOW}! A rule was skipped because a rule it requires failed.{STYLE_RESET}"),
+                }
+                if counted {
+                    skipped += 1;
+                }
+                unmet.insert(rule.target.clone(), (blocker, blocker_visibility));
+                continue;
+            }
+
+            // A rule that could not be run at all fails even if failure is okay
+            let rule_outcome = match &rule.tests {
+                // The cases passed are scored whether or not all of them passed
+                Some(tests) => self.run_test_cases(context, &rule, tests, path, log_dir.as_deref(), &env).ok().map(|(rule_outcome, cases_passed)| {
+                    if is_metric {
+                        Self::log_metric(&mut outcome.scores, &rule.target, &rule.metric, &cases_passed.to_string(), shown);
+                    }
+                    rule_outcome
+                }),
+                None => self.run_rule(context, &rule, path, log_dir.as_deref(), &env).ok(),
+            };
+
+            if let Some(RuleOutcome::Breached(breach)) = rule_outcome {
+                if counted {
+                    breached += 1;
+                }
+                outcome.breaches.insert(rule.target.clone(), toml::Value::String(breach.name().to_owned()));
+            }
+
+            if rule_outcome != Some(RuleOutcome::Passed) {
+                let stops = rule_outcome.is_none() || rule.fail_okay != Some(true);
+                if !counted && stops {
+                    // Rules that could not be run at all have already said that a rule failed
+                    if rule_outcome.is_some() {
+                        println!("{FG_RED}! A rule failed.{STYLE_RESET}");
                     }
-                },
-                Err(SubmissionFatal) => {
+                    count += 1;
+                }
+                if counted || stops {
                     failed += 1;
+                }
+                unmet.insert(rule.target.clone(), (rule.target.clone(), visibility));
+                if stops {
                     fatal = true;
-                    break;
+                    if !uses_requires {
+                        break;
+                    }
                 }
+                continue;
             }
 
-            if did_pass && is_metric {
+            if counted {
+                passed += 1;
+            }
+
+            if is_metric && rule.tests.is_none() {
                 let path = path.join(&rule.target);
                 let result = fs::read_to_string(&path).map_err(|err|
                     Error::io("Failed to read file", path, err)
                 );
 
-                match (rule.kind.as_ref(), result) {
-                    (Some(kind), Ok(result)) => Self::log_metric(&mut scores, &rule.target, &result, kind),
-                    (None, Ok(_)) => println!("{FG_RED}! Metric{STYLE_RESET} '{}' {FG_RED}has no kind.{STYLE_RESET}", rule.target),
-                    (_, Err(log)) => print!("{log}"),
+                match (rule.output, rule.metric.kind, result) {
+                    (Some(format), _, Ok(document)) => Self::log_metric_document(&mut outcome.scores, &rule, format, &document, shown),
+                    (None, Some(_), Ok(result)) => Self::log_metric(&mut outcome.scores, &rule.target, &rule.metric, &result, shown),
+                    (None, None, Ok(_)) if shown => println!("{FG_RED}! Metric{STYLE_RESET} '{}' {FG_RED}has no kind.{STYLE_RESET}", rule.target),
+                    (_, _, Err(log)) if shown => print!("{log}"),
+                    _ => (),
                 }
             }
         }
 
-        if fatal {
-            println!("{FG_RED}! Execution cannot continue beyond this error.{STYLE_RESET}");
+        match (fatal, uses_requires) {
+            (true, false) => println!("{FG_RED}! Execution cannot continue beyond this error.{STYLE_RESET}"),
+            (true, true)  => println!("{FG_RED}! Execution cannot continue beyond this ruleset.{STYLE_RESET}"),
+            (false, _)    => (),
         }
         println!("{}", util::Hline::Normal);
-        let not_reached = count-passed-failed;
-        println!("! {count} total targets - {passed} passed, {failed} failed, {not_reached} not reached.");
-
-        if fatal {
-            return Err(SubmissionFatal);
-        }
-
-        Ok(scores)
+        let not_reached = count-passed-failed-skipped;
+        println!(
+            "! {count} total targets - {passed} passed, {failed} failed ({breached} over limits), \
+            {skipped} skipped, {not_reached} not reached."
+        );
+
+        outcome.fatal = fatal;
+        outcome
     }
 
     pub fn run_on_submit(
@@ -433,13 +1167,14 @@ impl AsgnSpec {
         path: &Path,
         title: &str,
         is_metric: bool,
-    ) -> Option<Result<toml::value::Table, SubmissionFatal>>
+        subject: Option<&RuleSubject>,
+    ) -> Option<Result<RulesetOutcome, SubmissionFatal>>
     {
         match ruleset {
             Some(Ruleset { on_submit: Some(true) | None, .. }) => {
                 println!("{}", util::Hline::Bold);
                 println!("{FG_YELLOW}{TEXT_BOLD}{title}{STYLE_RESET}");
-                Some(self.run_ruleset(context, ruleset, path, is_metric))
+                Some(self.run_ruleset(context, ruleset, path, is_metric, subject))
             }
             _ => None,
         }
@@ -452,21 +1187,159 @@ impl AsgnSpec {
         path: &Path,
         title: &str,
         is_metric: bool,
-    ) -> Option<Result<toml::value::Table, SubmissionFatal>>
+        subject: Option<&RuleSubject>,
+    ) -> Option<Result<RulesetOutcome, SubmissionFatal>>
     {
         match ruleset {
             Some(Ruleset { on_grade: Some(true) | None, .. }) => {
                 println!("{}", util::Hline::Bold);
                 println!("{FG_YELLOW}{TEXT_BOLD}{title}{STYLE_RESET}");
-                Some(self.run_ruleset(context, ruleset, path, is_metric))
+                Some(self.run_ruleset(context, ruleset, path, is_metric, subject))
             }
             _ => None,
         }
     }
 
-    pub fn retrieve_sub(&self, dst_dir: &Path, username: &str) -> Result<(), Error> {
-        let sub_path = self.path.join(username);
+    fn is_pattern(entry: &str) -> bool {
+        entry.contains(['*', '?', '['])
+    }
+
+    /// An entry may be a file path, a directory, or a glob pattern.
+    fn resolve_entry(root: &Path, entry: &Path) -> Result<Vec<PathBuf>, Error> {
+        let text = entry.to_string_lossy();
+
+        if Self::is_pattern(&text) {
+            let pattern = glob::Pattern::new(&text).map_err(|err|
+                Error::custom(format!("Invalid file pattern '{text}': {err}"), CONTACT_INSTRUCTOR)
+            )?;
+            let options = glob::MatchOptions {
+                case_sensitive: true,
+                require_literal_separator: true,
+                require_literal_leading_dot: true,
+            };
+
+            let files: Vec<PathBuf> = util::files_under(root, root, true)?
+                .into_iter()
+                .filter(|file| pattern.matches_path_with(file, options))
+                .collect();
+
+            return match files.is_empty() {
+                true  => Err(FilePresenceErrorKind::NoMatch.at(root.join(entry))),
+                false => Ok(files),
+            };
+        }
+
+        let path = root.join(entry);
+        if path.is_dir() {
+            return util::files_under(root, &path, false);
+        }
 
+        FilePresenceErrorKind::assert_file(&path).map_err(|kind| kind.at(&path))?;
+        Ok(vec![entry.to_path_buf()])
+    }
+
+    pub fn resolve_optional(&self, root: &Path) -> Result<Vec<PathBuf>, Error> {
+        Self::resolve_present(root, &self.optional_file_list)
+    }
+
+    /// Missing required files are not an error.
+    pub fn resolve_available(&self, root: &Path) -> Result<Vec<PathBuf>, Error> {
+        let entries: Vec<PathBuf> = self.file_list.iter()
+            .chain(&self.optional_file_list)
+            .cloned()
+            .collect();
+        Self::resolve_present(root, &entries)
+    }
+
+    fn resolve_present(root: &Path, entries: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
+
+        for entry in entries {
+            let absent = Self::is_pattern(&entry.to_string_lossy()) || !root.join(entry).exists();
+            match Self::resolve_entry(root, entry) {
+                Ok(entry_files) => files.extend(entry_files),
+                Err(_) if absent => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    pub fn resolve_file_list(&self, root: &Path) -> Result<Vec<PathBuf>, ErrorLog> {
+        let mut log = ErrorLog::default();
+        let mut files = Vec::new();
+
+        for entry in &self.file_list {
+            match Self::resolve_entry(root, entry) {
+                Ok(entry_files) => files.extend(entry_files),
+                Err(err) => log.push(err),
+            }
+        }
+        log.into_result::<()>()?;
+
+        files.extend(self.resolve_optional(root)?);
+
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    pub fn copy_file_list(&self, src_dir: &Path, dst_dir: &Path) -> Result<Vec<PathBuf>, ErrorLog> {
+        let files = self.resolve_file_list(src_dir)?;
+
+        for file in &files {
+            util::copy_nested(src_dir, dst_dir, file)?;
+        }
+
+        Ok(files)
+    }
+
+    pub fn check_limits(&self, root: &Path, files: &[PathBuf]) -> Result<(), ErrorLog> {
+        let Some(limits) = &self.limits else {
+            return Ok(());
+        };
+
+        let mut log = ErrorLog::default();
+        let mut total: u64 = 0;
+
+        for file in files {
+            let path = root.join(file);
+            let size = fs::metadata(&path)
+                .map_err(|err| Error::io("Failed to stat file", &path, err))?
+                .len();
+            total += size;
+
+            if let Some(max) = limits.max_file_size {
+                if size > max {
+                    log.push(Error::file_too_large(file, size, max));
+                }
+            }
+
+            if let Some(allowed) = &limits.allowed_extensions {
+                let extension = file.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_default();
+                if !allowed.iter().any(|ext| ext.trim_start_matches('.') == extension) {
+                    log.push(Error::file_type_not_allowed(file, allowed));
+                }
+            }
+
+            if limits.text_only == Some(true) && !util::is_text_file(&path)? {
+                log.push(Error::file_not_text(file));
+            }
+        }
+
+        if let Some(max) = limits.max_total_size {
+            if total > max {
+                log.push(Error::submission_too_large(total, max));
+            }
+        }
+
+        log.into_result()
+    }
+
+    pub fn retrieve_from(&self, sub_path: &Path, dst_dir: &Path) -> Result<(), ErrorLog> {
         if dst_dir.is_dir() {
             fs::remove_dir_all(dst_dir).map_err(|err|
                 Error::io("Failed to remove directory", dst_dir, err)
@@ -483,24 +1356,7 @@ impl AsgnSpec {
             Error::io("Failed to create directory", dst_dir, err)
         )?;
 
-        for file_name in &self.file_list {
-            let src_path = sub_path.join(file_name);
-            let dst_path = dst_dir .join(file_name);
-            if src_path.is_dir() {
-                continue;
-            }
-
-            if !src_path.exists() {
-                return Err(Error::custom(
-                    format!("could not copy file {} to {}", src_path.display(), dst_path.display()),
-                    "File does not exist in the submission directory.".to_owned(),
-                ));
-            }
-
-            fs::copy(&src_path, &dst_path).map_err(|err|
-                Error::io("Failed to copy file", src_path, err)
-            )?;
-        }
+        self.copy_file_list(sub_path, dst_dir)?;
 
         Ok(())
     }
@@ -518,6 +1374,12 @@ pub struct SubmissionStatus {
     pub extension_days: i64,
 }
 
+pub struct SubmissionVersion {
+    pub number: usize,
+    pub time: DateTime<Local>,
+    pub path: PathBuf,
+}
+
 #[derive(Serialize, Deserialize)]
 struct GraceToml {
     pub value: i64,
@@ -528,6 +1390,17 @@ struct ExtensionToml {
     pub value: i64,
 }
 
+#[derive(Serialize, Deserialize)]
+struct SubmissionRecordToml {
+    pub time: toml::value::Datetime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawalToml {
+    pub username: String,
+    pub time: toml::value::Datetime,
+}
+
 
 impl<'ctx> SubmissionSlot<'ctx> {
     pub fn grace_path(&self) -> PathBuf {
@@ -538,9 +1411,65 @@ impl<'ctx> SubmissionSlot<'ctx> {
         self.base_path.join(".extension")
     }
 
-    pub fn file_paths(&self) -> impl '_ + Iterator<Item=PathBuf> {
-        self.asgn_spec.file_list.iter()
-            .map(|name| self.base_path.join(name))
+    pub fn record_path(&self) -> PathBuf {
+        self.base_path.join(".submission.toml")
+    }
+
+    pub fn receipt_path(&self) -> PathBuf {
+        self.base_path.join(".receipt.toml")
+    }
+
+    /// Lies outside the slot, where owners may add records but not list them. They could still
+    /// edit or remove one they know the name of, which `withdrawals_altered` detects.
+    pub fn withdrawn_path(&self) -> PathBuf {
+        self.asgn_spec.path.join(".info").join("withdrawn").join(self.base_path.file_name().unwrap())
+    }
+
+    pub fn log_path(&self) -> PathBuf {
+        self.asgn_spec.path.join(".info").join("logs").join(self.base_path.file_name().unwrap())
+    }
+
+    pub fn reset_logs(&self) -> Result<PathBuf, Error> {
+        let log_path = self.log_path();
+        if log_path.exists() {
+            fs::remove_dir_all(&log_path).map_err(|err|
+                Error::io("Failed to remove directory", &log_path, err)
+            )?;
+        }
+        Ok(log_path)
+    }
+
+    pub fn history_path(&self) -> PathBuf {
+        self.base_path.join(".history")
+    }
+
+    pub fn files(&self) -> Result<Vec<PathBuf>, ErrorLog> {
+        self.asgn_spec.resolve_file_list(&self.base_path)
+    }
+
+    /// Keeps only the slot's dot-prefixed records, as the file list may have changed since.
+    pub fn clear_files(&self) -> Result<(), Error> {
+        if !self.base_path.is_dir() {
+            return Ok(());
+        }
+
+        let entries = fs::read_dir(&self.base_path).map_err(|err|
+            Error::io("Failed to read directory", &self.base_path, err)
+        )?;
+
+        for entry in entries {
+            let entry = entry.map_err(|err| Error::io("Failed to read directory", &self.base_path, err))?;
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            let removed = if is_dir { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+            removed.map_err(|err| Error::io("Failed to remove file", &path, err))?;
+        }
+
+        Ok(())
     }
 
     pub fn get_grace(&self) -> Result<i64, Error> {
@@ -587,29 +1516,294 @@ impl<'ctx> SubmissionSlot<'ctx> {
         util::write_toml_file(&ExtensionToml { value }, self.extension_path())
     }
 
-    pub fn status(&self) -> Result<SubmissionStatus, Error> {
-        let submitted = self.file_paths().all(|p| p.is_file());
+    pub fn snapshot(&self, time: &DateTime<Local>) -> Result<PathBuf, ErrorLog> {
+        let history_path = self.history_path();
+        if !history_path.exists() {
+            fs::create_dir(&history_path).map_err(|err|
+                Error::io("Failed to create directory", &history_path, err)
+            )?;
+        }
 
-        let time: Option<i64> = if submitted {
-            let mut mtime: i64 = 0;
-            for path in self.file_paths() {
-                let meta = fs::metadata(&path).map_err(|err|{
-                    Error::io("Failed to stat file", path, err)
-                })?;
-                mtime = mtime.max(meta.mtime());
+        let stamp = time.format(SubmissionVersion::STAMP_FORMAT).to_string();
+        let snap_path = util::make_fresh_dir(&history_path, &stamp);
+        fs::create_dir(&snap_path).map_err(|err|
+            Error::io("Failed to create directory", &snap_path, err)
+        )?;
+
+        for file in self.files()? {
+            util::copy_nested(&self.base_path, &snap_path, &file)?;
+        }
+
+        Ok(snap_path)
+    }
+
+    /// The withdrawn area is set up by refreshing the assignment.
+    pub fn withdraw(&self, time: &DateTime<Local>) -> Result<PathBuf, ErrorLog> {
+        let files = self.files()?;
+
+        let withdrawn_path = self.withdrawn_path();
+
+        let stamp = time.format(SubmissionVersion::STAMP_FORMAT).to_string();
+        let dst_path = util::make_fresh_dir(&withdrawn_path, &stamp);
+        fs::create_dir(&dst_path).map_err(|err|
+            Error::io("Failed to create directory", &dst_path, err)
+        )?;
+
+        for file in &files {
+            util::copy_nested(&self.base_path, &dst_path, file)?;
+        }
+        self.clear_files()?;
+
+        for path in [self.record_path(), self.receipt_path()] {
+            if path.is_file() {
+                let moved = dst_path.join(path.file_name().unwrap());
+                fs::rename(&path, &moved).map_err(|err|
+                    Error::io("Failed to move file", &path, err)
+                )?;
             }
-            Some(mtime)
-        } else {
-            None
+        }
+
+        util::write_toml_file(
+            &WithdrawalToml {
+                username: self.context.username.clone(),
+                time: time.to_toml_datetime(),
+            },
+            dst_path.join("withdrawal.toml"),
+        )?;
+
+        Ok(dst_path)
+    }
+
+    pub fn withdrawals(&self) -> Result<Vec<WithdrawalToml>, Error> {
+        let withdrawn_path = self.withdrawn_path();
+        if !withdrawn_path.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let mut withdrawals: Vec<WithdrawalToml> = util::files_under(&withdrawn_path, &withdrawn_path, false)?
+            .into_iter()
+            .filter(|file| file.ends_with("withdrawal.toml"))
+            .map(|file| util::parse_toml_file(withdrawn_path.join(file)))
+            .collect::<Result<_, Error>>()?;
+
+        withdrawals.sort_by_key(|withdrawal| withdrawal.time.to_string());
+        Ok(withdrawals)
+    }
+
+    /// Owners can set back neither change times nor the modification time of the withdrawn
+    /// area, which is owned by the instructor.
+    pub fn withdrawals_altered(&self) -> Result<bool, Error> {
+        let withdrawn_path = self.withdrawn_path();
+        if !withdrawn_path.is_dir() {
+            return Ok(false);
+        }
+
+        let meta = fs::metadata(&withdrawn_path).map_err(|err|
+            Error::io("Failed to stat file", &withdrawn_path, err)
+        )?;
+        let modified: DateTime<Local> = meta.modified()
+            .map_err(|err| Error::io("Failed to stat file", &withdrawn_path, err))?
+            .into();
+        // Without records, any change since the area was created must have removed one
+        let mut last_written: Option<DateTime<Local>> = meta.created().ok().map(DateTime::from);
+
+        let records = util::files_under(&withdrawn_path, &withdrawn_path, false)?
+            .into_iter()
+            .filter(|file| file.ends_with("withdrawal.toml"));
+        for record in records {
+            let path = withdrawn_path.join(record);
+            let withdrawal: WithdrawalToml = util::parse_toml_file(&path)?;
+            let changed = Self::change_time(&path)?;
+            let recorded = withdrawal.time.try_into_chrono_date_time().ok_or_else(||
+                Error::bad_spec(&path, "Missing withdrawal time.")
+            )?;
+
+            if Self::unless_changed_after(recorded, changed) != recorded {
+                return Ok(true);
+            }
+            last_written = last_written.max(Some(changed));
+        }
+
+        Ok(last_written.is_some_and(|last| Self::unless_changed_after(last, modified) != last))
+    }
+
+    pub fn write_receipt(&self, time: &DateTime<Local>, snap_path: Option<&Path>) -> Result<Receipt, ErrorLog> {
+        let files = self.files()?;
+        let receipt = Receipt::compute(
+            &self.asgn_spec.name,
+            &self.context.username,
+            time.to_toml_datetime(),
+            &self.base_path,
+            &files,
+        )?;
+
+        receipt.write(self.receipt_path())?;
+        if let Some(snap_path) = snap_path {
+            receipt.write(snap_path.join(".receipt.toml"))?;
+        }
+
+        Ok(receipt)
+    }
+
+    /// Oldest first, numbered from 1.
+    pub fn versions(&self) -> Result<Vec<SubmissionVersion>, Error> {
+        let history_path = self.history_path();
+        if !history_path.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        let dir_iter = fs::read_dir(&history_path).map_err(|err|
+            Error::io("Failed to read dir", &history_path, err)
+        )?;
+
+        let mut entries: Vec<(String, DateTime<Local>, PathBuf)> = Vec::new();
+        for entry in dir_iter {
+            let entry = entry.map_err(|err|
+                Error::io("Failed to read dir entry", &history_path, err)
+            )?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(time) = SubmissionVersion::parse_stamp(&name) else {
+                continue;
+            };
+            entries.push((name, time, entry.path()));
+        }
+
+        // Snapshots made within the same second are disambiguated by `make_fresh_dir`'s
+        // numeric suffix, so shorter names sort first.
+        entries.sort_by(|(a_name, a_time, _), (b_name, b_time, _)|
+            a_time.cmp(b_time)
+                .then_with(|| a_name.len().cmp(&b_name.len()))
+                .then_with(|| a_name.cmp(b_name))
+        );
+
+        Ok(entries.into_iter()
+            .enumerate()
+            .map(|(idx, (_, time, path))| SubmissionVersion { number: idx + 1, time, path })
+            .collect())
+    }
+
+    /// `None` is the most recent submission.
+    pub fn version_path(&self, version: Option<usize>) -> Result<PathBuf, Error> {
+        let Some(number) = version else {
+            return Ok(self.base_path.clone());
         };
 
-        let turn_in_time = if let Some(seconds) = time {
-            let turn_in = Local.timestamp_opt(seconds, 0)
-                .earliest()
-                .ok_or(Error::custom("Impossible time conversion", CONTACT_INSTRUCTOR))?;
-            Some(turn_in)
-        } else {
-            None
+        self.versions()?
+            .into_iter()
+            .find(|v| v.number == number)
+            .map(|v| v.path)
+            .ok_or_else(|| Error::no_such_version(&self.asgn_spec.name, number))
+    }
+
+    pub fn history(&self) -> Result<Table, Error> {
+        let mut table = Table::new(["VERSION".to_owned(), "TIME".to_owned(), "FILES".to_owned()]);
+
+        table.extend(self.versions()?.into_iter().map(|version| {
+            let files = util::files_under(&version.path, &version.path, true)
+                .unwrap_or_default()
+                .iter()
+                .map(|name| name.display())
+                .join(" ");
+            [version.number.to_string(), version.time.to_string(), files]
+        }))?;
+
+        Ok(table)
+    }
+
+    pub fn due_date(&self, status: &SubmissionStatus) -> Result<Option<DateTime<Local>>, Error> {
+        Context::offset_date(
+            self.asgn_spec.due_date.as_ref(),
+            status.extension_days + status.grace_days,
+        )
+    }
+
+    /// Pushed back like the due date, so that extensions outlast the assignment-wide close date.
+    pub fn close_date(&self, status: &SubmissionStatus) -> Result<Option<DateTime<Local>>, Error> {
+        Context::offset_date(
+            self.asgn_spec.close_date.as_ref(),
+            status.extension_days + status.grace_days,
+        )
+    }
+
+    pub fn after_close(&self) -> Result<bool, Error> {
+        let close_date = self.close_date(&self.status()?)?;
+
+        Ok(close_date.map(|date| {
+            Local::now().signed_duration_since(date) > chrono::Duration::zero()
+        }).unwrap_or(false))
+    }
+
+    pub fn late_penalty(&self, status: &SubmissionStatus) -> Result<Option<f64>, Error> {
+        let Some(policy) = &self.asgn_spec.late_policy else {
+            return Ok(None);
+        };
+
+        let late_by = self.due_date(status)?.and_then(|due| status.time_past(&due));
+
+        Ok(late_by.map(|late_by| policy.penalty(late_by)))
+    }
+
+    pub fn set_turn_in_time(&self, time: &DateTime<Local>) -> Result<(), Error> {
+        util::write_toml_file(
+            &SubmissionRecordToml { time: time.to_toml_datetime() },
+            self.record_path(),
+        )
+    }
+
+    /// The record is writable by the student, so its change time wins if much later.
+    pub fn get_turn_in_time(&self) -> Result<Option<DateTime<Local>>, Error> {
+        let path = self.record_path();
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let record: SubmissionRecordToml = util::parse_toml_file(&path)?;
+        let recorded = record.time.try_into_chrono_date_time().ok_or_else(||
+            Error::bad_spec(&path, "Missing submission time.")
+        )?;
+
+        Ok(Some(Self::unless_changed_after(recorded, Self::change_time(&path)?)))
+    }
+
+    fn change_time(path: &Path) -> Result<DateTime<Local>, Error> {
+        let ctime = fs::metadata(path)
+            .map_err(|err| Error::io("Failed to stat file", path, err))?
+            .ctime();
+        Local.timestamp_opt(ctime, 0)
+            .earliest()
+            .ok_or(Error::custom("Impossible time conversion", CONTACT_INSTRUCTOR))
+    }
+
+    fn unless_changed_after(time: DateTime<Local>, changed: DateTime<Local>) -> DateTime<Local> {
+        match changed.signed_duration_since(time) > Duration::minutes(1) {
+            true  => changed,
+            false => time,
+        }
+    }
+
+    pub fn status(&self) -> Result<SubmissionStatus, Error> {
+        let files = self.files().ok();
+
+        // The submitted files stay writable by the student, so a file changed after the
+        // recorded time moves the turn-in time to when it was changed
+        let turn_in_time = match &files {
+            Some(files) => {
+                let mut changed: Option<DateTime<Local>> = None;
+                for path in files.iter().map(|file| self.base_path.join(file)) {
+                    changed = changed.max(Some(Self::change_time(&path)?));
+                }
+
+                match (self.get_turn_in_time()?, changed) {
+                    (Some(recorded), Some(changed)) => Some(Self::unless_changed_after(recorded, changed)),
+                    (Some(recorded), None) => Some(recorded),
+                    // A slot with a receipt or history was submitted with a record, so one that
+                    // has gone missing is not trusted, and only change times are used
+                    (None, _) if self.receipt_path().exists() || self.history_path().exists() => changed,
+                    // Slots submitted before turn-in times were recorded fall back to file mtimes
+                    (None, _) => self.legacy_turn_in_time(files)?,
+                }
+            }
+            None => None,
         };
 
         Ok(SubmissionStatus {
@@ -618,6 +1812,21 @@ impl<'ctx> SubmissionSlot<'ctx> {
             extension_days: self.get_extension()?,
         })
     }
+
+    fn legacy_turn_in_time(&self, files: &[PathBuf]) -> Result<Option<DateTime<Local>>, Error> {
+        let mut mtime: i64 = 0;
+        for path in files.iter().map(|file| self.base_path.join(file)) {
+            let meta = fs::metadata(&path).map_err(|err|{
+                Error::io("Failed to stat file", path, err)
+            })?;
+            mtime = mtime.max(meta.mtime());
+        }
+
+        let turn_in = Local.timestamp_opt(mtime, 0)
+            .earliest()
+            .ok_or(Error::custom("Impossible time conversion", CONTACT_INSTRUCTOR))?;
+        Ok(Some(turn_in))
+    }
 }
 
 impl SubmissionStatus {
@@ -660,8 +1869,121 @@ impl SubmissionStatus {
     }
 }
 
+impl SubmissionVersion {
+    const STAMP_FORMAT: &'static str = "%Y-%m-%dT%H:%M:%S";
+
+    fn parse_stamp(name: &str) -> Option<DateTime<Local>> {
+        let stamp = name.split('.').next()?;
+        let naive = chrono::NaiveDateTime::parse_from_str(stamp, Self::STAMP_FORMAT).ok()?;
+        Local.from_local_datetime(&naive).earliest()
+    }
+}
+
+impl LatePolicy {
+    /// Partial days and hours count as whole ones.
+    pub fn penalty(&self, late_by: Duration) -> f64 {
+        if late_by <= Duration::zero() {
+            return 0.0;
+        }
+
+        let late_hours = (late_by.num_seconds() as f64 / 3600.0).ceil();
+        let late_days  = (late_by.num_seconds() as f64 / 86400.0).ceil();
+
+        if let Some(zero_after) = self.zero_after_days {
+            if late_by.num_seconds() as f64 > zero_after * 86400.0 {
+                return 100.0;
+            }
+        }
+
+        let penalty = late_days  * self.percent_per_day .unwrap_or_default()
+                    + late_hours * self.percent_per_hour.unwrap_or_default();
+
+        penalty.min(self.max_percent.unwrap_or(100.0)).clamp(0.0, 100.0)
+    }
+}
+
+impl StatBlock {
+    pub fn apply_penalty(&mut self, penalty: Option<f64>) {
+        self.late_penalty = penalty;
+
+        let Some(penalty) = penalty else {
+            self.adjusted_scores = None;
+            self.adjusted_total = None;
+            return;
+        };
+
+        let factor = 1.0 - penalty / 100.0;
+        self.adjusted_total = self.total.map(|total| total * factor);
+        self.adjusted_scores = Some(self.scores.iter()
+            .map(|(name, value)| {
+                let adjusted = match value {
+                    toml::Value::Integer(int) => toml::Value::Float(*int as f64 * factor),
+                    toml::Value::Float(float) => toml::Value::Float(float * factor),
+                    other => other.clone(),
+                };
+                (name.clone(), adjusted)
+            })
+            .collect());
+    }
+
+    pub fn final_total(&self) -> Option<f64> {
+        self.adjusted_total.or(self.total)
+    }
+}
+
 impl StatBlockSet {
     pub fn get_block(&self, username: &str) -> Option<&StatBlock> {
         self.stat_block.iter().flatten().find(|block| block.username == username)
     }
+
+    pub fn get_team_block(&self, members: &[String]) -> Option<&StatBlock> {
+        self.stat_block.iter().flatten().find(|block| members.contains(&block.username))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(per_day: Option<f64>, per_hour: Option<f64>, max: Option<f64>, zero_after: Option<f64>) -> LatePolicy {
+        LatePolicy {
+            percent_per_day: per_day,
+            percent_per_hour: per_hour,
+            max_percent: max,
+            zero_after_days: zero_after,
+        }
+    }
+
+    #[test]
+    fn on_time_and_early_submissions_have_no_penalty() {
+        let policy = policy(Some(10.0), Some(1.0), None, Some(1.0));
+        assert_eq!(policy.penalty(Duration::zero()), 0.0);
+        assert_eq!(policy.penalty(Duration::seconds(-1)), 0.0);
+        assert_eq!(policy.penalty(Duration::days(-30)), 0.0);
+    }
+
+    #[test]
+    fn partial_days_and_hours_count_as_whole_ones() {
+        let daily = policy(Some(10.0), None, None, None);
+        assert_eq!(daily.penalty(Duration::seconds(1)), 10.0);
+        assert_eq!(daily.penalty(Duration::days(1)), 10.0);
+        assert_eq!(daily.penalty(Duration::days(1) + Duration::seconds(1)), 20.0);
+
+        let hourly = policy(None, Some(2.0), None, None);
+        assert_eq!(hourly.penalty(Duration::minutes(61)), 4.0);
+    }
+
+    #[test]
+    fn penalty_is_capped() {
+        assert_eq!(policy(Some(10.0), None, Some(25.0), None).penalty(Duration::days(5)), 25.0);
+        assert_eq!(policy(Some(60.0), None, None, None).penalty(Duration::days(3)), 100.0);
+        assert_eq!(policy(Some(-10.0), None, None, None).penalty(Duration::days(3)), 0.0);
+    }
+
+    #[test]
+    fn late_beyond_zero_after_days_loses_everything() {
+        let policy = policy(Some(10.0), None, Some(30.0), Some(2.0));
+        assert_eq!(policy.penalty(Duration::days(2)), 20.0);
+        assert_eq!(policy.penalty(Duration::days(2) + Duration::seconds(1)), 100.0);
+    }
 }
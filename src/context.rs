@@ -1,6 +1,6 @@
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env::current_dir,
     os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
@@ -14,8 +14,8 @@ use itertools::Itertools;
 
 use crate::{
     error::{Error, ErrorLog},
-    asgn_spec::{AsgnSpec, AsgnSpecToml, SubmissionSlot},
-    util,
+    asgn_spec::{AsgnSpec, AsgnSpecToml, SubmissionSlot, StatBlock, StatBlockSet},
+    util::{self, color::{FG_YELLOW, STYLE_RESET}},
     table::Table,
     act::instructor::InstructorAct,
 };
@@ -49,6 +49,17 @@ pub enum Role {
     Other,
 }
 
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Instructor => "instructor",
+            Role::Grader     => "grader",
+            Role::Student    => "student",
+            Role::Other      => "other",
+        }
+    }
+}
+
 pub struct Context {
     // Determined through input
     pub instructor: String,
@@ -56,7 +67,6 @@ pub struct Context {
     pub exe_path: PathBuf,
 
     // Determined through system calls
-    pub uid: u32,
     pub username: String,
     pub time: DateTime<Local>,
     pub cwd: PathBuf,
@@ -91,8 +101,22 @@ impl Context {
     pub fn populate_catalog(&mut self) {
         for asgn_name in &self.manifest {
             let spec_path = self.base_path.join(asgn_name);
-            self.catalog.insert(asgn_name.clone(), AsgnSpec::load(spec_path));
+            let spec = AsgnSpec::load(spec_path).and_then(|spec| self.check_teams(spec));
+            self.catalog.insert(asgn_name.clone(), spec);
+        }
+    }
+
+    /// Team slots share a directory with individual slots.
+    fn check_teams(&self, spec: AsgnSpec) -> Result<AsgnSpec, Error> {
+        for team in spec.all_teams() {
+            if self.members.contains(&team.name) && !team.members.contains(&team.name) {
+                return Err(Error::bad_spec(
+                    &spec.path,
+                    &format!("Team '{}' has the same name as a course member outside of the team.", team.name),
+                ));
+            }
         }
+        Ok(spec)
     }
 
     pub fn catalog_get<'a>(&'a self, asgn_name: &str) -> Result<&'a AsgnSpec, Error> {
@@ -160,7 +184,6 @@ impl Context {
             instructor,
             base_path,
             exe_path,
-            uid,
             username,
             time,
             cwd,
@@ -185,7 +208,7 @@ impl Context {
             .collect::<ErrorLog>()
     }
 
-    pub fn grader_facl(&self, student: Option<&str>) -> Result<Vec<util::FaclEntry>, Error> {
+    pub fn grader_facl(&self, students: &[&str]) -> Result<Vec<util::FaclEntry>, Error> {
         let mut facl_list: Vec<util::FaclEntry> = Vec::new();
 
         facl_list.push(util::FaclEntry {
@@ -195,17 +218,15 @@ impl Context {
             exe: true,
         });
 
-        if let Some(student) = student {
-            facl_list.push(util::FaclEntry {
-                username: student.to_owned(),
-                read: true,
-                write: true,
-                exe: true,
-            });
-        }
+        facl_list.extend(students.iter().map(|&student| util::FaclEntry {
+            username: student.to_owned(),
+            read: true,
+            write: true,
+            exe: true,
+        }));
 
         let graders_exclusive = self.graders.iter()
-            .filter(|&grader| Some(grader.as_str()) != student && grader != &self.instructor)
+            .filter(|&grader| !students.contains(&grader.as_str()) && grader != &self.instructor)
             .map(|grader| util::FaclEntry {
                 username: grader.to_owned(),
                 read: true,
@@ -230,7 +251,7 @@ impl Context {
 
         let dirs = [
             ("public",  0o755, Vec::new()),
-            ("private", 0o700, self.grader_facl(None)?),
+            ("private", 0o700, self.grader_facl(&[])?),
         ];
 
         for (name, flags, facl) in dirs {
@@ -260,7 +281,7 @@ impl Context {
 
         let dirs = [
             ("public", 0o755, Vec::new()),
-            ("private", 0o700, self.grader_facl(None)?),
+            ("private", 0o700, self.grader_facl(&[])?),
         ];
 
         for (name, flags, facl) in dirs {
@@ -268,6 +289,18 @@ impl Context {
             util::recursive_refresh_dir(&path, flags, facl.iter())?;
         }
 
+        // Graders record logs when they copy submissions, so they need to write here
+        let log_facl: Vec<util::FaclEntry> = self.grader_facl(&[])?
+            .into_iter()
+            .map(|entry| util::FaclEntry { write: true, ..entry })
+            .collect();
+        util::recursive_refresh_dir(asgn_spec_path.join("logs"), 0o700, log_facl.iter())?;
+
+        // Slot owners record their own withdrawals, but may only add to their withdrawn area, whose
+        // times reveal any later edits
+        let withdrawn_path = asgn_spec_path.join("withdrawn");
+        util::refresh_dir(&withdrawn_path, 0o711, iter::empty())?;
+
         let internal_path = asgn_spec_path.join(".internal");
         let score_build_path = internal_path.join("score_build");
         util::recursive_refresh_dir(internal_path, 0o700, iter::empty())?;
@@ -275,21 +308,69 @@ impl Context {
 
         let asgn_path = self.base_path.join(asgn_name);
 
-        for member in &self.members {
-            let asgn_sub_path = asgn_path.join(member);
-            let facl_list = self.grader_facl(Some(member))?;
+        let spec = self.catalog_get(asgn_name).ok();
+
+        let mut slots: Vec<(&str, Vec<&str>)> = self.members.iter()
+            .filter(|member| spec.and_then(|spec| spec.team_of(member)).is_none())
+            .map(|member| (member.as_str(), vec![member.as_str()]))
+            .collect();
+
+        slots.extend(spec.iter().flat_map(|spec| spec.all_teams()).map(|team|
+            (team.name.as_str(), team.members.iter().map(String::as_str).collect())
+        ));
+
+        for (slot_name, owners) in slots {
+            let asgn_sub_path = asgn_path.join(slot_name);
+            let facl_list = self.grader_facl(&owners)?;
 
             util::refresh_dir(asgn_sub_path.clone(), 0o700, facl_list.iter())?;
 
+            let withdrawn_facl: Vec<util::FaclEntry> = self.grader_facl(&[])?
+                .into_iter()
+                .chain(owners.iter().map(|&owner| util::FaclEntry {
+                    username: owner.to_owned(),
+                    read: false,
+                    write: true,
+                    exe: true,
+                }))
+                .collect();
+            util::refresh_dir(withdrawn_path.join(slot_name), 0o1700, withdrawn_facl.iter())?;
+
             let extension_path = asgn_sub_path.join(".grace");
             util::refresh_file(extension_path, 0o777, "value = 0")?;
             let extension_path = asgn_sub_path.join(".extension");
             util::refresh_file(extension_path, 0o755, "value = 0")?;
         }
 
+        if let Ok(spec) = self.catalog_get(asgn_name) {
+            self.report_limit_violations(spec);
+        }
+
         Ok(())
     }
 
+    fn report_limit_violations(&self, asgn: &AsgnSpec) {
+        // Teammates share a slot, which is only reported once
+        let mut seen = HashSet::new();
+        for member in &self.members {
+            let slot = self.get_slot(asgn, member);
+            if !seen.insert(slot.base_path.clone()) {
+                continue;
+            }
+            let Ok(files) = slot.files() else {
+                continue;
+            };
+
+            if let Err(log) = asgn.check_limits(&slot.base_path, &files) {
+                let slot_name = slot.base_path.file_name().unwrap_or_default().to_string_lossy();
+                println!("{FG_YELLOW}Submission of '{}' by '{slot_name}' exceeds the assignment's limits:{STYLE_RESET}", asgn.name);
+                for err in log {
+                    println!("{err}");
+                }
+            }
+        }
+    }
+
     pub fn refresh(&self) -> Result<(), Error> {
         self.refresh_root()?;
 
@@ -299,14 +380,18 @@ impl Context {
     }
 
     pub fn get_slot<'a>(&'a self, asgn: &'a AsgnSpec, username: &str) -> SubmissionSlot<'a> {
+        let slot_name = asgn.team_of(username)
+            .map(|team| team.name.as_str())
+            .unwrap_or(username);
+
         SubmissionSlot {
             context: self,
             asgn_spec: asgn,
-            base_path: self.base_path.join(&asgn.name).join(username),
+            base_path: self.base_path.join(&asgn.name).join(slot_name),
         }
     }
 
-    fn offset_date(date: Option<&DateTime<Local>>, offset: i64) -> Result<Option<DateTime<Local>>, Error> {
+    pub fn offset_date(date: Option<&DateTime<Local>>, offset: i64) -> Result<Option<DateTime<Local>>, Error> {
         if let Some(&date) = date {
             let offset_date = if offset >= 0 {
                 date.naive_local()
@@ -355,43 +440,50 @@ impl Context {
     }
 
     pub fn submission_summary_row(&self, asgn: &AsgnSpec, username: &str) -> Vec<String> {
-        let status = self.get_slot(asgn, username).status().unwrap();
+        let slot = self.get_slot(asgn, username);
+        let status = slot.status().unwrap();
         let lateness = status.versus(asgn.due_date.as_ref());
 
         let extension = status.extension_days;
         let grace = status.grace_days;
 
+        let altered = match slot.withdrawals_altered().unwrap_or_default() {
+            true  => " (records altered)",
+            false => "",
+        };
+        let withdrawn = slot.withdrawals().unwrap_or_default()
+            .last()
+            .map(|withdrawal| format!("{} by {}{altered}", withdrawal.time, withdrawal.username))
+            .or_else(|| (!altered.is_empty()).then(|| format!("NONE{altered}")));
+
+        let total = util::parse_toml_file::<StatBlockSet>(asgn.path.join(".info").join("score.toml")).ok()
+            .and_then(|stats| stats.get_block(username).and_then(StatBlock::final_total))
+            .map(|total| format!("{total:.2}"));
+
         vec![
             asgn.name.clone(),
             username.to_owned(),
             lateness,
             extension.to_string(),
             grace.to_string(),
+            Table::option_repr(withdrawn),
+            Table::option_repr(total),
         ]
     }
 
     pub fn normal_summary_row(&self, asgn: &AsgnSpec, username: &str) -> Result<Vec<String>, Error> {
-        let sub_dir = self.base_path.join(&asgn.name).join(username);
-
-        let slot = SubmissionSlot {
-            context: self,
-            asgn_spec: asgn,
-            base_path: sub_dir,
-        };
+        let slot = self.get_slot(asgn, username);
 
         let status = slot.status().unwrap();
         let due_date = asgn.due_date;
-        let extension = status.extension_days;
-        let grace = status.grace_days;
-        let bump = extension+grace;
 
-        let ext_due_date = Self::offset_date(asgn.due_date.as_ref(), bump)?;
+        let ext_due_date = slot.due_date(&status)?;
         let lateness = status.versus(ext_due_date.as_ref());
 
         let active =
             if !asgn.active { "DISABLED" }
             else if asgn.before_open() { "BEFORE OPEN" }
-            else if asgn.after_close() { "AFTER CLOSE" }
+            else if slot.after_close()? { "AFTER CLOSE" }
             else { "ENABLED" };
 
         let naive_due_date = due_date.map(|due| {
@@ -412,7 +504,7 @@ impl Context {
     }
 
     pub fn list_subs(&self, asgn_name: Option<&str>, username: Option<&str>) -> Result<(), Error> {
-        let header = ["ASSIGNMENT", "USER", "SUBMISSION STATUS", "EXTENSION", "GRACE"].map(str::to_owned);
+        let header = ["ASSIGNMENT", "USER", "SUBMISSION STATUS", "EXTENSION", "GRACE", "LAST WITHDRAWAL", "TOTAL"].map(str::to_owned);
 
         let mut table = Table::new(header);
 
@@ -491,13 +583,7 @@ impl Context {
             .filter_map(|name| self.catalog.get(&name.clone()) )
             .filter_map(|asgn| asgn.as_ref().ok() )
             .map(|asgn|{
-                let sub_dir = self.base_path.join(&asgn.name).join(&self.username);
-
-                let slot = SubmissionSlot {
-                    context: self,
-                    asgn_spec: asgn,
-                    base_path: sub_dir,
-                };
+                let slot = self.get_slot(asgn, &self.username);
 
                 let status = slot.status().unwrap();
 
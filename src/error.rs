@@ -9,7 +9,7 @@ pub const VALID_DATE: &str = "Please enter a valid date";
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FilePresenceErrorKind {
-    NotFound, FileIsDir, FileIsOther,
+    NotFound, FileIsDir, FileIsOther, NoMatch,
 }
 
 impl FilePresenceErrorKind {
@@ -19,6 +19,7 @@ impl FilePresenceErrorKind {
             NotFound => "File not found",
             FileIsDir => "File is actually a directory",
             FileIsOther => "File is neither a normal file nor a directory",
+            NoMatch => "No files match the pattern",
         }
     }
 
@@ -28,6 +29,7 @@ impl FilePresenceErrorKind {
             NotFound => "Please ensure that the file exists",
             FileIsDir => "Please ensure that the file is not a directory",
             FileIsOther => "Please ensure that the file is actually a file",
+            NoMatch => "Please ensure that at least one matching file exists",
         }
     }
 
@@ -143,6 +145,13 @@ impl Error {
         )
     }
 
+    pub fn sandbox_unavailable(err: io::Error) -> Self {
+        Self::new(
+            format!("Failed to set up the rule sandbox:{STYLE_RESET} {err}"),
+            "The rule was not run. Sandboxed rules require unprivileged user namespaces, so please contact the instructor.",
+        )
+    }
+
     pub fn subprocess(desc: &str, err: String) -> Self {
         Self::new(
             format!("Subprocess {desc} failed:{STYLE_RESET} {err}"),
@@ -192,6 +201,61 @@ impl Error {
         )
     }
 
+    pub fn file_too_large(path: impl AsRef<Path>, size: u64, max: u64) -> Self {
+        Self::new(
+            format!("File{STYLE_RESET} {} {FG_RED}is {size} bytes, exceeding the per-file limit of {max} bytes.", path.as_ref().display()),
+            "Please remove unnecessary content, such as binaries or generated files.",
+        )
+    }
+
+    pub fn submission_too_large(size: u64, max: u64) -> Self {
+        Self::new(
+            format!("Submission is {size} bytes, exceeding the total limit of {max} bytes."),
+            "Please remove unnecessary content, such as binaries or generated files.",
+        )
+    }
+
+    pub fn file_not_text(path: impl AsRef<Path>) -> Self {
+        Self::new(
+            format!("File{STYLE_RESET} {} {FG_RED}is not a text file.", path.as_ref().display()),
+            "Only text files may be submitted for this assignment.",
+        )
+    }
+
+    pub fn file_type_not_allowed(path: impl AsRef<Path>, allowed: &[String]) -> Self {
+        Self::new(
+            format!("File{STYLE_RESET} {} {FG_RED}does not have an allowed extension.", path.as_ref().display()),
+            format!(
+                "Allowed extensions are: {}",
+                allowed.iter()
+                    .map(|ext| if ext.trim_start_matches('.').is_empty() { "(none)" } else { ext.as_str() })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        )
+    }
+
+    pub fn unsupported_archive(path: impl AsRef<Path>) -> Self {
+        Self::new(
+            format!("Archive{STYLE_RESET} {} {FG_RED}is not of a supported format.", path.as_ref().display()),
+            "Please provide a .tar, .tar.gz, .tgz or .zip archive.",
+        )
+    }
+
+    pub fn archive_too_many_entries(path: impl AsRef<Path>, max: usize) -> Self {
+        Self::new(
+            format!("Archive{STYLE_RESET} {} {FG_RED}holds more than the limit of {max} entries.", path.as_ref().display()),
+            "Please remove unnecessary content, such as binaries or generated files.",
+        )
+    }
+
+    pub fn unexpected_file(path: impl AsRef<Path>) -> Self {
+        Self::new(
+            format!("File{STYLE_RESET} {} {FG_RED}is not part of this assignment's file list.", path.as_ref().display()),
+            "Please remove it from the archive, or check the assignment details for the expected files.",
+        )
+    }
+
     pub fn no_setup(name: &str) -> Self {
         Self::new(
             format!("Setup files are not available for assignment{STYLE_RESET} '{name}'{FG_RED}."),
@@ -199,6 +263,13 @@ impl Error {
         )
     }
 
+    pub fn no_such_version(asgn_name: &str, version: usize) -> Self {
+        Self::new(
+            format!("Assignment{STYLE_RESET} '{asgn_name}' {FG_RED}has no submitted version{STYLE_RESET} {version}{FG_RED}."),
+            "Use the history command to list the available versions.",
+        )
+    }
+
     pub fn no_such_member(name: &str) -> Self {
         Self::new(
             format!("User{STYLE_RESET} '{name}' {FG_RED} is not a member of this course."),
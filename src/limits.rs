@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex, Once},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde_derive::{Serialize, Deserialize};
+
+/// USER_HZ, which Linux fixes at 100
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const MAX_CAPTURE: u64 = 64 * 1024 * 1024;
+
+/// Limits on a rule's process group. Times are in seconds and memory in resident bytes.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RuleLimits {
+    pub timeout: Option<u64>,
+    pub cpu_time: Option<u64>,
+    pub memory: Option<u64>,
+    pub max_processes: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LimitBreach {
+    Timeout, CpuTime, Memory, Processes,
+}
+
+impl LimitBreach {
+    /// The name recorded for the breach in score files.
+    pub fn name(&self) -> &'static str {
+        use LimitBreach::*;
+        match self {
+            Timeout   => "timeout",
+            CpuTime   => "cpu_time",
+            Memory    => "memory",
+            Processes => "max_processes",
+        }
+    }
+
+    pub fn describe(&self, limits: &RuleLimits) -> String {
+        use LimitBreach::*;
+        match self {
+            Timeout   => format!("exceeded its time limit of {}s", limits.timeout.unwrap_or_default()),
+            CpuTime   => format!("exceeded its CPU time limit of {}s", limits.cpu_time.unwrap_or_default()),
+            Memory    => format!("exceeded its memory limit of {} bytes", limits.memory.unwrap_or_default()),
+            Processes => format!("exceeded its limit of {} processes", limits.max_processes.unwrap_or_default()),
+        }
+    }
+}
+
+pub enum LimitedExit {
+    Exited(ExitStatus),
+    Breached(LimitBreach),
+}
+
+#[derive(Default)]
+struct GroupUsage {
+    cpu_ticks: u64,
+    memory: u64,
+    processes: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct ProcStat {
+    ppid: u32,
+    pgid: u32,
+    /// Including that of reaped children
+    cpu_ticks: u64,
+    /// Tells a process apart from a later one that reuses its pid
+    start_time: u64,
+}
+
+impl ProcStat {
+    fn parse(stat: &str) -> Option<Self> {
+        // The command name may itself contain spaces and parentheses, but is the only field
+        // that can, so the fields proper begin after the last ')'
+        let (_, fields) = stat.rsplit_once(')')?;
+        let fields: Vec<&str> = fields.split_whitespace().collect();
+        let field = |idx: usize| fields.get(idx)?.parse::<u64>().ok();
+
+        Some(Self {
+            ppid: field(1)?.try_into().ok()?,
+            pgid: field(2)?.try_into().ok()?,
+            cpu_ticks: (11..15).map(field).sum::<Option<u64>>()?,
+            start_time: field(19)?,
+        })
+    }
+
+    fn read(pid: u32) -> Option<Self> {
+        Self::parse(&fs::read_to_string(format!("/proc/{pid}/stat")).ok()?)
+    }
+}
+
+type Members = HashMap<u32, u64>;
+
+static ACTIVE_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static FORWARD_SIGNALS: Once = Once::new();
+
+impl RuleLimits {
+    pub fn or(&self, fallback: &Self) -> Self {
+        Self {
+            timeout: self.timeout.or(fallback.timeout),
+            cpu_time: self.cpu_time.or(fallback.cpu_time),
+            memory: self.memory.or(fallback.memory),
+            max_processes: self.max_processes.or(fallback.max_processes),
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The command is given no input, as a background process group would be stopped by reading it.
+    pub fn run_at(&self, mut cmd: Command, path: impl AsRef<Path>, log: Option<File>, shown: bool) -> io::Result<LimitedExit> {
+        cmd.current_dir(path.as_ref()).stdin(Stdio::null());
+        if log.is_some() {
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+        }
+
+        let mut child = self.spawn(cmd)?;
+
+        let tees = log.map(|log| {
+            let log = Arc::new(Mutex::new(log));
+            let (stdout, stderr): (Box<dyn Write + Send>, Box<dyn Write + Send>) = match shown {
+                true  => (Box::new(io::stdout()), Box::new(io::stderr())),
+                false => (Box::new(io::sink()), Box::new(io::sink())),
+            };
+            let stdout = child.stdout.take().map(|out| Self::tee(out, stdout, log.clone()));
+            let stderr = child.stderr.take().map(|err| Self::tee(err, stderr, log));
+            [stdout, stderr]
+        });
+
+        let exit = self.wait(child);
+
+        for tee in tees.into_iter().flatten().flatten() {
+            let _ = tee.join();
+        }
+
+        exit
+    }
+
+    pub fn run_captured(&self, mut cmd: Command, path: impl AsRef<Path>, input: File) -> io::Result<(LimitedExit, Vec<u8>)> {
+        cmd.current_dir(path.as_ref())
+            .stdin(input)
+            .stdout(Stdio::piped());
+
+        let mut child = self.spawn(cmd)?;
+
+        let reader = child.stdout.take().map(|out| thread::spawn(move || {
+            let mut out = out.take(MAX_CAPTURE);
+            let mut buffer = Vec::new();
+            let _ = out.read_to_end(&mut buffer);
+            let _ = io::copy(&mut out.into_inner(), &mut io::sink());
+            buffer
+        }));
+
+        let exit = self.wait(child);
+        let output = reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+        exit.map(|exit| (exit, output))
+    }
+
+    /// Only CPU time is a kernel limit. The kernel's memory and process limits count address
+    /// space and every process of the user, so those are checked on the group instead.
+    fn spawn(&self, mut cmd: Command) -> io::Result<Child> {
+        Self::forward_signals();
+
+        let cpu_time = self.cpu_time;
+
+        cmd.process_group(0);
+        // SAFETY: setrlimit is async-signal-safe and the closure allocates nothing.
+        unsafe {
+            cmd.pre_exec(move || {
+                // The hard limit is a second past the soft one, so that SIGXCPU is sent first
+                if let Some(secs) = cpu_time {
+                    Self::set_rlimit(libc::RLIMIT_CPU, secs, secs + 1)?;
+                }
+                Ok(())
+            });
+        }
+
+        let child = cmd.spawn()?;
+        if let Ok(mut groups) = ACTIVE_GROUPS.lock() {
+            groups.push(child.id());
+        }
+        Ok(child)
+    }
+
+    fn set_rlimit(resource: libc::__rlimit_resource_t, soft: u64, hard: u64) -> io::Result<()> {
+        let limit = libc::rlimit { rlim_cur: soft, rlim_max: hard };
+        // SAFETY: `limit` outlives the call.
+        match unsafe { libc::setrlimit(resource, &limit) } {
+            -1 => Err(io::Error::last_os_error()),
+            _  => Ok(()),
+        }
+    }
+
+    /// Rules run in the background of the terminal, so do not receive its signals.
+    fn forward_signals() {
+        use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+        FORWARD_SIGNALS.call_once(|| {
+            let Ok(mut signals) = signal_hook::iterator::Signals::new([SIGINT, SIGTERM, SIGHUP]) else {
+                return;
+            };
+            thread::spawn(move || {
+                if let Some(signal) = signals.forever().next() {
+                    if let Ok(groups) = ACTIVE_GROUPS.lock() {
+                        for &pgid in groups.iter() {
+                            Self::kill_group(pgid, &Members::new());
+                        }
+                    }
+                    std::process::exit(128 + signal);
+                }
+            });
+        });
+    }
+
+    fn tee(mut src: impl Read + Send + 'static, mut dst: impl Write + Send + 'static, log: Arc<Mutex<File>>)
+    -> thread::JoinHandle<()>
+    {
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while let Ok(count @ 1..) = src.read(&mut buffer) {
+                let _ = dst.write_all(&buffer[..count]);
+                let _ = dst.flush();
+                if let Ok(mut log) = log.lock() {
+                    let _ = log.write_all(&buffer[..count]);
+                }
+            }
+        })
+    }
+
+    fn wait(&self, mut child: Child) -> io::Result<LimitedExit> {
+        let pgid = child.id();
+        let exit = self.wait_within_limits(&mut child);
+
+        if let Ok(mut groups) = ACTIVE_GROUPS.lock() {
+            groups.retain(|&active| active != pgid);
+        }
+        exit
+    }
+
+    fn wait_within_limits(&self, child: &mut Child) -> io::Result<LimitedExit> {
+        let pgid = child.id();
+
+        if self.is_unlimited() {
+            let status = child.wait();
+            Self::kill_group(pgid, &Members::new());
+            return status.map(LimitedExit::Exited);
+        }
+
+        let start = Instant::now();
+        // Every process seen to belong to the rule, including those that left its group
+        let mut members = Members::new();
+
+        loop {
+            if let Some((status, exit_usage)) = Self::try_wait(pgid)? {
+                Self::kill_group(pgid, &members);
+                // A limit may be passed between two checks, or by the kernel killing the
+                // process at its hard CPU time limit, so the usage at exit is checked too
+                let breach = match status.signal() {
+                    Some(libc::SIGXCPU) => Some(LimitBreach::CpuTime),
+                    _ => self.breach(&exit_usage, Duration::ZERO),
+                };
+                return Ok(match breach {
+                    Some(breach) => LimitedExit::Breached(breach),
+                    None => LimitedExit::Exited(status),
+                });
+            }
+
+            let usage = Self::group_usage(pgid, &mut members);
+            if let Some(breach) = self.breach(&usage, start.elapsed()) {
+                Self::kill_group(pgid, &members);
+                let _ = child.kill();
+                let _ = child.wait();
+                return Ok(LimitedExit::Breached(breach));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn try_wait(pid: u32) -> io::Result<Option<(ExitStatus, GroupUsage)>> {
+        let mut status = 0;
+        // SAFETY: rusage is plain data, for which all zeroes is valid.
+        let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+        // SAFETY: both pointers outlive the call.
+        match unsafe { libc::wait4(pid as libc::pid_t, &mut status, libc::WNOHANG, &mut rusage) } {
+            -1 => Err(io::Error::last_os_error()),
+            0  => Ok(None),
+            _  => {
+                let ticks = |time: libc::timeval|
+                    time.tv_sec as u64 * CLOCK_TICKS_PER_SEC + time.tv_usec as u64 * CLOCK_TICKS_PER_SEC / 1_000_000;
+                let usage = GroupUsage {
+                    cpu_ticks: ticks(rusage.ru_utime) + ticks(rusage.ru_stime),
+                    memory: rusage.ru_maxrss as u64 * 1024,
+                    processes: 0,
+                };
+                Ok(Some((ExitStatus::from_raw(status), usage)))
+            }
+        }
+    }
+
+    fn breach(&self, usage: &GroupUsage, elapsed: Duration) -> Option<LimitBreach> {
+        if self.timeout.is_some_and(|secs| elapsed.as_secs() >= secs) {
+            Some(LimitBreach::Timeout)
+        } else if self.cpu_time.is_some_and(|secs| usage.cpu_ticks >= secs * CLOCK_TICKS_PER_SEC) {
+            Some(LimitBreach::CpuTime)
+        } else if self.memory.is_some_and(|bytes| usage.memory > bytes) {
+            Some(LimitBreach::Memory)
+        } else if self.max_processes.is_some_and(|count| usage.processes > count) {
+            Some(LimitBreach::Processes)
+        } else {
+            None
+        }
+    }
+
+    /// Includes descendants that have moved to another group, adding them to `members`.
+    fn group_usage(pgid: u32, members: &mut Members) -> GroupUsage {
+        let mut usage = GroupUsage::default();
+
+        let Ok(entries) = fs::read_dir("/proc") else {
+            return usage;
+        };
+
+        let stats: HashMap<u32, ProcStat> = entries.flatten()
+            .filter_map(|entry| {
+                let pid = entry.file_name().to_str()?.parse().ok()?;
+                Some((pid, ProcStat::read(pid)?))
+            })
+            .collect();
+
+        // Descendants are found a generation at a time, so repeat until no more are found
+        let mut found = true;
+        while found {
+            found = false;
+            for (&pid, stat) in &stats {
+                if !members.contains_key(&pid) && (stat.pgid == pgid || members.contains_key(&stat.ppid)) {
+                    members.insert(pid, stat.start_time);
+                    found = true;
+                }
+            }
+        }
+
+        for (pid, stat) in stats.iter().filter(|(pid, stat)| members.get(pid) == Some(&stat.start_time)) {
+            usage.processes += 1;
+            usage.cpu_ticks += stat.cpu_ticks;
+
+            let rss_kb = fs::read_to_string(format!("/proc/{pid}/status")).ok()
+                .and_then(|status| status.lines()
+                    .find_map(|line| line.strip_prefix("VmRSS:"))
+                    .and_then(|rss| rss.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+                );
+            usage.memory += rss_kb.unwrap_or_default() * 1024;
+        }
+
+        usage
+    }
+
+    fn kill_group(pgid: u32, members: &Members) {
+        // SAFETY: neither call touches memory.
+        unsafe {
+            libc::killpg(pgid as libc::pid_t, libc::SIGKILL);
+        }
+
+        for (&pid, &start_time) in members {
+            if ProcStat::read(pid).is_some_and(|stat| stat.start_time == start_time) {
+                // SAFETY: as above.
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT_TAIL: &str = "S 100 200 200 0 -1 4194560 10 0 0 0 7 3 2 1 20 0 1 0 5555 1000 100";
+
+    #[test]
+    fn parses_stat_fields() {
+        let stat = ProcStat::parse(&format!("300 (make) {STAT_TAIL}")).unwrap();
+        assert_eq!(stat, ProcStat { ppid: 100, pgid: 200, cpu_ticks: 13, start_time: 5555 });
+    }
+
+    #[test]
+    fn parses_command_names_with_spaces_and_parentheses() {
+        let expected = ProcStat::parse(&format!("300 (make) {STAT_TAIL}"));
+        assert_eq!(ProcStat::parse(&format!("300 (a) b) {STAT_TAIL}")), expected);
+        assert_eq!(ProcStat::parse(&format!("300 (x ) 1 2 3 (y) {STAT_TAIL}")), expected);
+        assert_eq!(ProcStat::parse(&format!("300 ()) {STAT_TAIL}")), expected);
+    }
+
+    #[test]
+    fn rejects_truncated_or_malformed_stats() {
+        assert_eq!(ProcStat::parse(""), None);
+        assert_eq!(ProcStat::parse("300 make S 100 200"), None);
+        assert_eq!(ProcStat::parse("300 (make) S 100 200 200"), None);
+        assert_eq!(ProcStat::parse(&format!("300 (make) {}", STAT_TAIL.replace("200", "-2"))), None);
+    }
+}
@@ -0,0 +1,355 @@
+use std::{collections::HashMap, fmt};
+
+use itertools::Itertools;
+use serde_derive::{Serialize, Deserialize};
+
+/// `bounded` and `percent` scores must lie within the `min` and `max` (0 to 100 for percent),
+/// while those of `int` and `float` only set the range they are scaled within for the total.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    Bool, Int, Float, String, Percent, Bounded, Ordinal,
+}
+
+impl MetricKind {
+    pub fn name(&self) -> &'static str {
+        use MetricKind::*;
+        match self {
+            Bool    => "bool",
+            Int     => "int",
+            Float   => "float",
+            String  => "string",
+            Percent => "percent",
+            Bounded => "bounded",
+            Ordinal => "ordinal",
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, MetricKind::Bool | MetricKind::Int | MetricKind::Float | MetricKind::Percent | MetricKind::Bounded)
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        matches!(self, MetricKind::Percent | MetricKind::Bounded)
+    }
+}
+
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Every score of a metric maps to the same variant, so numbers are never compared to text.
+#[derive(Clone, PartialEq, PartialOrd, Debug)]
+pub enum MetricKey {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MetricSpec {
+    pub kind: Option<MetricKind>,
+    pub levels: Option<Vec<String>>,
+    pub weight: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// The max of test case rules, found when they are loaded
+    #[serde(skip)]
+    pub default_max: Option<f64>,
+    /// Set for test cases the caller cannot read, which still count toward the total
+    #[serde(skip)]
+    pub default_max_unknown: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NamedMetric {
+    pub name: String,
+    #[serde(flatten)]
+    pub spec: MetricSpec,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Toml, Json,
+}
+
+impl DocumentFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DocumentFormat::Toml => "toml",
+            DocumentFormat::Json => "json",
+        }
+    }
+
+    pub fn parse_document(&self, text: &str) -> Result<HashMap<String, String>, String> {
+        let values = match self {
+            DocumentFormat::Toml => toml::from_str::<toml::value::Table>(text)
+                .map_err(|err| err.message().to_owned())?
+                .into_iter()
+                .map(|(name, value)| match value {
+                    toml::Value::String(text) => (name, text),
+                    other => (name, other.to_string()),
+                })
+                .collect(),
+            DocumentFormat::Json => serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(text)
+                .map_err(|err| err.to_string())?
+                .into_iter()
+                .map(|(name, value)| match value {
+                    serde_json::Value::String(text) => (name, text),
+                    other => (name, other.to_string()),
+                })
+                .collect(),
+        };
+        Ok(values)
+    }
+}
+
+impl fmt::Display for DocumentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl MetricSpec {
+    pub fn problem(&self) -> Option<String> {
+        if self.weight.is_some_and(|weight| weight.is_nan() || weight < 0.0) {
+            return Some("must not have a negative weight".to_owned());
+        }
+
+        if let Some(kind) = self.kind {
+            if !kind.is_numeric() && (self.min.is_some() || self.max.is_some()) {
+                return Some(format!("is of kind '{kind}', which cannot have a min or max"));
+            }
+            if kind == MetricKind::Bounded && self.max.is_none() {
+                return Some("is of kind 'bounded' but has no max".to_owned());
+            }
+            if kind != MetricKind::Ordinal && self.levels.is_some() {
+                return Some(format!("is of kind '{kind}', which cannot have levels"));
+            }
+            if kind == MetricKind::String && self.weight.is_some() {
+                return Some("is of kind 'string', which cannot be weighted".to_owned());
+            }
+            if self.weight.is_some() && !self.is_scaled() {
+                return Some(format!("is of kind '{kind}' and weighted, so needs a max to scale it by"));
+            }
+        }
+
+        if self.kind == Some(MetricKind::Ordinal) {
+            if self.levels().is_empty() {
+                return Some("is of kind 'ordinal' but has no levels".to_owned());
+            }
+            if !self.levels().iter().all_unique() {
+                return Some("lists the same level more than once".to_owned());
+            }
+        }
+
+        let (min, max) = self.scale();
+        if min.is_nan() || max.is_some_and(|max| max.is_nan() || max <= min) {
+            return Some("must have a max above its min (0 by default)".to_owned());
+        }
+
+        None
+    }
+
+    /// Booleans default to a max of 1 and percentages to 100.
+    fn scale(&self) -> (f64, Option<f64>) {
+        let max = match self.kind {
+            Some(MetricKind::Bool) => Some(self.max.unwrap_or(1.0)),
+            Some(MetricKind::Percent) => Some(self.max.unwrap_or(100.0)),
+            _ => self.max.or(self.default_max),
+        };
+        (self.min.unwrap_or(0.0), max)
+    }
+
+    fn levels(&self) -> &[String] {
+        self.levels.as_deref().unwrap_or_default()
+    }
+
+    pub fn parse(&self, result: &str) -> Result<toml::Value, String> {
+        let Some(kind) = self.kind else {
+            return Err("belongs to a metric with no kind".to_owned());
+        };
+
+        let parse_float = |text: &str| text.trim().parse::<f64>().ok().filter(|float| !float.is_nan());
+
+        let score = match kind {
+            MetricKind::Bool    => result.parse::<bool>().ok().map(toml::Value::Boolean),
+            MetricKind::Int     => result.parse::<i64>().ok().map(toml::Value::Integer),
+            MetricKind::Float | MetricKind::Bounded => parse_float(result).map(toml::Value::Float),
+            MetricKind::Percent => parse_float(result.strip_suffix('%').unwrap_or(result)).map(toml::Value::Float),
+            MetricKind::String  => Some(toml::Value::String(result.to_owned())),
+            MetricKind::Ordinal => self.levels().iter()
+                .find(|level| *level == result)
+                .map(|level| toml::Value::String(level.clone())),
+        };
+
+        let Some(score) = score else {
+            return Err(match kind {
+                MetricKind::Ordinal => format!("is not one of the levels {}", self.levels().join(", ")),
+                _ => format!("failed to parse into '{kind}'"),
+            });
+        };
+
+        let number = match score {
+            toml::Value::Integer(int) => int as f64,
+            toml::Value::Float(float) => float,
+            _ => return Ok(score),
+        };
+
+        match self.scale() {
+            _ if !kind.is_bounded() => Ok(score),
+            (min, _) if number < min => Err(format!("is below the min of {min}")),
+            (_, Some(max)) if number > max => Err(format!("is above the max of {max}")),
+            _ => Ok(score),
+        }
+    }
+
+    /// Scores that do not match the kind, such as those from before it was changed, have no key.
+    pub fn key(&self, score: &toml::Value) -> Option<MetricKey> {
+        use toml::Value;
+        match (self.kind?, score) {
+            (MetricKind::Bool, Value::Boolean(passed)) => Some(MetricKey::Number(f64::from(u8::from(*passed)))),
+            (MetricKind::Int, Value::Integer(int)) => Some(MetricKey::Number(*int as f64)),
+            (MetricKind::Float | MetricKind::Percent | MetricKind::Bounded, Value::Float(float)) => Some(MetricKey::Number(*float)),
+            (MetricKind::String, Value::String(text)) => Some(MetricKey::Text(text.clone())),
+            (MetricKind::Ordinal, Value::String(level)) => self.levels().iter()
+                .position(|known| known == level)
+                .map(|idx| MetricKey::Number(idx as f64)),
+            _ => None,
+        }
+    }
+
+    /// Integers and floats are only scaled if given a max.
+    pub fn is_scaled(&self) -> bool {
+        match self.kind {
+            Some(MetricKind::Bool | MetricKind::Percent | MetricKind::Bounded | MetricKind::Ordinal) => true,
+            Some(MetricKind::Int | MetricKind::Float) => self.max.or(self.default_max).is_some() || self.default_max_unknown,
+            Some(MetricKind::String) | None => false,
+        }
+    }
+
+    /// Scores outside of the range scale outside of 0 and 1.
+    pub fn fraction(&self, score: &toml::Value) -> Option<f64> {
+        if !self.is_scaled() {
+            return None;
+        }
+
+        let MetricKey::Number(number) = self.key(score)? else {
+            return None;
+        };
+
+        match self.kind? {
+            MetricKind::Ordinal => match self.levels().len() {
+                1 => Some(1.0),
+                count => Some(number / (count - 1) as f64),
+            },
+            _ => {
+                let (min, max) = self.scale();
+                Some((number - min) / (max? - min))
+            }
+        }
+    }
+
+    pub fn display(&self, score: &toml::Value) -> String {
+        match (self.kind, score) {
+            (Some(MetricKind::Percent), toml::Value::Float(float)) => format!("{float}%"),
+            (_, toml::Value::String(text)) => text.clone(),
+            (_, other) => other.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(kind: MetricKind) -> MetricSpec {
+        MetricSpec { kind: Some(kind), ..MetricSpec::default() }
+    }
+
+    #[test]
+    fn parses_each_kind() {
+        assert_eq!(spec(MetricKind::Bool).parse("true"), Ok(toml::Value::Boolean(true)));
+        assert_eq!(spec(MetricKind::Int).parse("-3"), Ok(toml::Value::Integer(-3)));
+        assert_eq!(spec(MetricKind::Float).parse("2.5"), Ok(toml::Value::Float(2.5)));
+        assert_eq!(spec(MetricKind::Percent).parse("42%"), Ok(toml::Value::Float(42.0)));
+        assert_eq!(spec(MetricKind::Percent).parse("42"), Ok(toml::Value::Float(42.0)));
+        assert_eq!(spec(MetricKind::String).parse("fast"), Ok(toml::Value::String("fast".to_owned())));
+
+        let ordinal = MetricSpec { levels: Some(vec!["low".to_owned(), "high".to_owned()]), ..spec(MetricKind::Ordinal) };
+        assert_eq!(ordinal.parse("high"), Ok(toml::Value::String("high".to_owned())));
+        assert!(ordinal.parse("medium").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_scores() {
+        assert!(spec(MetricKind::Bool).parse("yes").is_err());
+        assert!(spec(MetricKind::Int).parse("1.5").is_err());
+        assert!(spec(MetricKind::Float).parse("NaN").is_err());
+        assert!(MetricSpec::default().parse("1").is_err());
+    }
+
+    #[test]
+    fn scores_above_the_max_are_extra_credit() {
+        let int = MetricSpec { max: Some(10.0), ..spec(MetricKind::Int) };
+        assert_eq!(int.parse("12"), Ok(toml::Value::Integer(12)));
+        assert_eq!(int.fraction(&toml::Value::Integer(12)), Some(1.2));
+        assert_eq!(int.fraction(&toml::Value::Integer(5)), Some(0.5));
+    }
+
+    #[test]
+    fn bounded_scores_outside_the_range_are_rejected() {
+        let bounded = MetricSpec { max: Some(10.0), ..spec(MetricKind::Bounded) };
+        assert_eq!(bounded.parse("10"), Ok(toml::Value::Float(10.0)));
+        assert!(bounded.parse("11").is_err());
+        assert!(bounded.parse("-1").is_err());
+        assert_eq!(bounded.fraction(&toml::Value::Float(2.5)), Some(0.25));
+
+        assert!(spec(MetricKind::Percent).parse("101%").is_err());
+        assert!(spec(MetricKind::Percent).parse("-1").is_err());
+        let extra = MetricSpec { max: Some(110.0), ..spec(MetricKind::Percent) };
+        assert_eq!(extra.parse("105%"), Ok(toml::Value::Float(105.0)));
+    }
+
+    #[test]
+    fn only_ranged_scores_have_fractions() {
+        assert_eq!(spec(MetricKind::Int).fraction(&toml::Value::Integer(37)), None);
+        assert_eq!(spec(MetricKind::String).fraction(&toml::Value::String("x".to_owned())), None);
+        assert_eq!(spec(MetricKind::Bool).fraction(&toml::Value::Boolean(true)), Some(1.0));
+        assert_eq!(spec(MetricKind::Percent).fraction(&toml::Value::Float(25.0)), Some(0.25));
+
+        let ranged = MetricSpec { min: Some(10.0), max: Some(20.0), ..spec(MetricKind::Float) };
+        assert_eq!(ranged.fraction(&toml::Value::Float(15.0)), Some(0.5));
+
+        let levels = ["F", "C", "A"].map(str::to_owned).to_vec();
+        let ordinal = MetricSpec { levels: Some(levels), ..spec(MetricKind::Ordinal) };
+        assert_eq!(ordinal.fraction(&toml::Value::String("C".to_owned())), Some(0.5));
+        assert_eq!(ordinal.fraction(&toml::Value::String("A".to_owned())), Some(1.0));
+    }
+
+    #[test]
+    fn scores_of_the_wrong_type_have_no_key() {
+        assert_eq!(spec(MetricKind::Int).key(&toml::Value::Float(1.0)), None);
+        assert_eq!(spec(MetricKind::Bool).key(&toml::Value::Boolean(false)), Some(MetricKey::Number(0.0)));
+    }
+
+    #[test]
+    fn checks_specs_for_problems() {
+        assert_eq!(MetricSpec { max: Some(2.0), ..spec(MetricKind::Bool) }.problem(), None);
+        assert_eq!(MetricSpec { max: Some(10.0), weight: Some(2.0), ..spec(MetricKind::Int) }.problem(), None);
+
+        assert!(MetricSpec { weight: Some(2.0), ..spec(MetricKind::Int) }.problem().is_some());
+        assert!(MetricSpec { weight: Some(-1.0), ..spec(MetricKind::Bool) }.problem().is_some());
+        assert!(MetricSpec { max: Some(0.0), ..spec(MetricKind::Int) }.problem().is_some());
+        assert!(MetricSpec { max: Some(f64::NAN), ..spec(MetricKind::Int) }.problem().is_some());
+        assert!(spec(MetricKind::Bounded).problem().is_some());
+        assert!(MetricSpec { min: Some(5.0), max: Some(1.0), ..spec(MetricKind::Bounded) }.problem().is_some());
+        assert!(MetricSpec { max: Some(1.0), ..spec(MetricKind::String) }.problem().is_some());
+        assert!(spec(MetricKind::Ordinal).problem().is_some());
+
+        let repeated = ["a", "a"].map(str::to_owned).to_vec();
+        assert!(MetricSpec { levels: Some(repeated), ..spec(MetricKind::Ordinal) }.problem().is_some());
+    }
+}
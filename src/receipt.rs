@@ -0,0 +1,118 @@
+use std::path::{Component, Path, PathBuf};
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::{
+    error::Error,
+    util,
+    table::Table,
+};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileDigest {
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Receipt {
+    pub assignment: String,
+    pub username: String,
+    pub time: toml::value::Datetime,
+    pub file: Vec<FileDigest>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DigestMatch {
+    Match, Mismatch, Missing,
+}
+
+impl DigestMatch {
+    pub fn description(&self) -> &'static str {
+        use DigestMatch::*;
+        match self {
+            Match    => "MATCH",
+            Mismatch => "MISMATCH",
+            Missing  => "MISSING",
+        }
+    }
+}
+
+pub struct DigestCheck<'r> {
+    pub digest: &'r FileDigest,
+    pub stored: Option<String>,
+    pub status: DigestMatch,
+}
+
+impl Receipt {
+    pub fn compute(
+        assignment: &str,
+        username: &str,
+        time: toml::value::Datetime,
+        dir: &Path,
+        file_list: &[PathBuf],
+    ) -> Result<Self, Error> {
+        let file = file_list.iter()
+            .map(|name| Ok(FileDigest {
+                path: name.clone(),
+                sha256: util::sha256_file(dir.join(name))?,
+            }))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(Self {
+            assignment: assignment.to_owned(),
+            username: username.to_owned(),
+            time,
+            file,
+        })
+    }
+
+    /// Rejects receipts whose paths could reach outside the submission.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let receipt: Self = util::parse_toml_file(&path)?;
+
+        let is_plain = |file: &Path| file.components().next().is_some()
+            && file.components().all(|part| matches!(part, Component::Normal(_)));
+        if let Some(digest) = receipt.file.iter().find(|digest| !is_plain(&digest.path)) {
+            return Err(Error::custom(
+                format!(
+                    "Receipt at '{}' lists the file '{}', which is not a relative path within the submission.",
+                    path.as_ref().display(), digest.path.display(),
+                ),
+                "Please provide an unmodified receipt.",
+            ));
+        }
+
+        Ok(receipt)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        util::write_toml_file(self, path)
+    }
+
+    pub fn verify(&self, dir: &Path) -> Result<Vec<DigestCheck<'_>>, Error> {
+        self.file.iter()
+            .map(|digest| {
+                let path = dir.join(&digest.path);
+                if !path.is_file() {
+                    return Ok(DigestCheck { digest, stored: None, status: DigestMatch::Missing });
+                }
+                let stored = util::sha256_file(path)?;
+                let status = if stored == digest.sha256 { DigestMatch::Match } else { DigestMatch::Mismatch };
+                Ok(DigestCheck { digest, stored: Some(stored), status })
+            })
+            .collect()
+    }
+
+    pub fn matches(&self, dir: &Path) -> Result<bool, Error> {
+        Ok(self.verify(dir)?.iter().all(|check| check.status == DigestMatch::Match))
+    }
+
+    pub fn table(&self) -> Result<Table, Error> {
+        let mut table = Table::new(["FILE".to_owned(), "SHA-256".to_owned()]);
+        table.extend(self.file.iter().map(|digest|
+            [digest.path.display().to_string(), digest.sha256.clone()]
+        ))?;
+        Ok(table)
+    }
+}
@@ -0,0 +1,427 @@
+use std::{
+    ffi::{CString, CStr},
+    fs,
+    io,
+    path::{Component, Path},
+    process::Command,
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    ptr,
+};
+
+use libc::{c_uint, sock_filter, sock_fprog};
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xC000_003E;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xC000_00B7;
+
+/// These fail with EPERM.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_mount, libc::SYS_umount2, libc::SYS_pivot_root, libc::SYS_chroot,
+    libc::SYS_unshare, libc::SYS_setns, libc::SYS_ptrace,
+    libc::SYS_process_vm_readv, libc::SYS_process_vm_writev,
+    libc::SYS_open_tree, libc::SYS_move_mount, libc::SYS_fsopen, libc::SYS_fsconfig,
+    libc::SYS_fsmount, libc::SYS_fspick, libc::SYS_mount_setattr,
+    libc::SYS_io_uring_setup, libc::SYS_io_uring_enter, libc::SYS_io_uring_register,
+    libc::SYS_bpf, libc::SYS_perf_event_open, libc::SYS_userfaultfd,
+    libc::SYS_keyctl, libc::SYS_add_key, libc::SYS_request_key,
+    libc::SYS_init_module, libc::SYS_finit_module, libc::SYS_delete_module,
+    libc::SYS_kexec_load, libc::SYS_reboot, libc::SYS_swapon, libc::SYS_swapoff,
+];
+
+/// Where the root is put together. Sources are opened first, so it may hide them, but only
+/// once in the new mount namespace, as mounts cannot be bound from another.
+const STAGING: &str = "/tmp";
+
+const SYSTEM_DIRS: &[&str] = &["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/libx32", "/etc", "/opt"];
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/full", "/dev/random", "/dev/urandom"];
+const DEVICE_LINKS: &[(&str, &str)] = &[
+    ("/dev/fd", "/proc/self/fd"),
+    ("/dev/stdin", "/proc/self/fd/0"),
+    ("/dev/stdout", "/proc/self/fd/1"),
+    ("/dev/stderr", "/proc/self/fd/2"),
+];
+const EMPTY_DIRS: &[&str] = &["/tmp", "/dev/shm"];
+
+enum MountKind {
+    Bind { source: CString, source_fd: libc::c_int, writable: bool, is_dir: bool },
+    Tmpfs,
+    Proc,
+}
+
+struct StagedMount {
+    target: CString,
+    dirs: Vec<CString>,
+    depth: usize,
+    kind: MountKind,
+}
+
+/// Prepared ahead, so that the child only makes syscalls between `fork` and `exec`.
+pub struct Sandbox {
+    work_dir: CString,
+    staging: CString,
+    mounts: Vec<StagedMount>,
+    /// By target and staged path
+    links: Vec<(CString, CString)>,
+    uid_map: String,
+    gid_map: String,
+    filter: Vec<sock_filter>,
+}
+
+impl Sandbox {
+    /// Only the system directories and course tree (read-only), `work_dir`, and an empty `/tmp`
+    /// are visible. There is no network, and only the sandbox's own processes can be seen.
+    pub fn new(work_dir: &Path, course_dir: &Path) -> io::Result<Self> {
+        let uid = users::get_effective_uid();
+        let gid = users::get_effective_gid();
+
+        let mut sandbox = Self {
+            work_dir: Self::c_path(work_dir)?,
+            staging: CString::new(STAGING)?,
+            mounts: Vec::new(),
+            links: Vec::new(),
+            uid_map: format!("{uid} {uid} 1"),
+            gid_map: format!("{gid} {gid} 1"),
+            filter: Self::seccomp_filter()?,
+        };
+
+        for dir in SYSTEM_DIRS.iter().map(Path::new) {
+            match fs::symlink_metadata(dir) {
+                Ok(meta) if meta.is_symlink() => sandbox.link(&fs::read_link(dir)?, dir)?,
+                Ok(meta) if meta.is_dir() => sandbox.bind(dir, false)?,
+                _ => (),
+            }
+        }
+        for device in DEVICES.iter().map(Path::new).filter(|device| device.exists()) {
+            sandbox.bind(device, true)?;
+        }
+        for (link, target) in DEVICE_LINKS {
+            sandbox.link(Path::new(target), Path::new(link))?;
+        }
+        for dir in EMPTY_DIRS {
+            sandbox.stage(Path::new(dir), MountKind::Tmpfs)?;
+        }
+        sandbox.stage(Path::new("/proc"), MountKind::Proc)?;
+        sandbox.bind(course_dir, false)?;
+        sandbox.bind(work_dir, true)?;
+
+        // Mounts are made outermost first, so that none hides another
+        sandbox.mounts.sort_by_key(|mount| mount.depth);
+        Ok(sandbox)
+    }
+
+    /// If the sandbox cannot be entered, spawning fails rather than running unconfined.
+    pub fn apply(mut self, cmd: &mut Command) {
+        cmd.env("TMPDIR", self.work_dir.to_str().unwrap_or("/tmp"));
+        // SAFETY: `enter` only makes syscalls on data prepared before the fork.
+        unsafe {
+            cmd.pre_exec(move || self.enter());
+        }
+    }
+
+    fn c_path(path: &Path) -> io::Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    fn staged(path: &Path) -> io::Result<CString> {
+        let mut staged = STAGING.as_bytes().to_vec();
+        staged.extend_from_slice(path.as_os_str().as_bytes());
+        Ok(CString::new(staged)?)
+    }
+
+    fn stage(&mut self, path: &Path, kind: MountKind) -> io::Result<()> {
+        if !path.is_absolute() || path.components().any(|part| part == Component::ParentDir) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' is not a plain absolute path", path.display())));
+        }
+
+        let is_dir = !matches!(kind, MountKind::Bind { is_dir: false, .. });
+        let mut dirs: Vec<&Path> = path.ancestors().skip(usize::from(!is_dir)).collect();
+        dirs.reverse();
+
+        self.mounts.push(StagedMount {
+            target: Self::staged(path)?,
+            dirs: dirs.into_iter().map(Self::staged).collect::<io::Result<_>>()?,
+            depth: path.components().count(),
+            kind,
+        });
+        Ok(())
+    }
+
+    fn bind(&mut self, path: &Path, writable: bool) -> io::Result<()> {
+        let is_dir = fs::metadata(path)?.is_dir();
+        self.stage(path, MountKind::Bind { source: Self::c_path(path)?, source_fd: -1, writable, is_dir })
+    }
+
+    fn fd_path(fd: libc::c_int, buf: &mut [u8; 32]) -> &CStr {
+        const PREFIX: &[u8] = b"/proc/self/fd/";
+        buf[..PREFIX.len()].copy_from_slice(PREFIX);
+
+        let digits = fd.max(0).checked_ilog10().unwrap_or(0) as usize + 1;
+        let mut rest = fd.max(0);
+        for digit in buf[PREFIX.len()..PREFIX.len() + digits].iter_mut().rev() {
+            *digit = b'0' + (rest % 10) as u8;
+            rest /= 10;
+        }
+        buf[PREFIX.len() + digits] = 0;
+
+        CStr::from_bytes_until_nul(buf).unwrap_or_default()
+    }
+
+    fn link(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        self.links.push((Self::c_path(target)?, Self::staged(path)?));
+        Ok(())
+    }
+
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn seccomp_filter() -> io::Result<Vec<sock_filter>> {
+        let stmt = |code: u32, k: u32| sock_filter { code: code as u16, jt: 0, jf: 0, k };
+        let jump = |code: u32, k: u32, jt: u8, jf: u8| sock_filter { code: code as u16, jt, jf, k };
+        let deny = libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA);
+
+        // Offsets of `arch`, `nr` and the low half of `args[0]` within `struct seccomp_data`
+        let mut filter = vec![
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 4),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, AUDIT_ARCH, 1, 0),
+            stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0),
+        ];
+
+        // The x32 ABI would otherwise reach the same syscalls under different numbers
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([
+            jump(libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K, 0x4000_0000, 0, 1),
+            stmt(libc::BPF_RET | libc::BPF_K, deny),
+        ]);
+
+        // Sockets bound to paths ignore read-only mounts, so no Unix sockets may be made,
+        // though socket pairs still can
+        filter.extend([
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, libc::SYS_socket as u32, 0, 4),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 16),
+            jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, libc::AF_UNIX as u32, 0, 1),
+            stmt(libc::BPF_RET | libc::BPF_K, deny),
+            stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, 0),
+        ]);
+
+        for &syscall in DENIED_SYSCALLS {
+            filter.extend([
+                jump(libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K, syscall as u32, 0, 1),
+                stmt(libc::BPF_RET | libc::BPF_K, deny),
+            ]);
+        }
+
+        filter.push(stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW));
+        Ok(filter)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn seccomp_filter() -> io::Result<Vec<sock_filter>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "sandboxing is not supported on this architecture"))
+    }
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        match result {
+            -1 => Err(io::Error::last_os_error()),
+            _  => Ok(()),
+        }
+    }
+
+    fn write_proc_file(path: &CStr, contents: &str) -> io::Result<()> {
+        // SAFETY: `path` is NUL-terminated and the buffer outlives the write.
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            Self::check(fd)?;
+            let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+            libc::close(fd);
+            match written {
+                -1 => Err(io::Error::last_os_error()),
+                _  => Ok(()),
+            }
+        }
+    }
+
+    /// Forks, with the parent exiting as the child does. The parent closes its descriptors, so
+    /// that the command's pipes close with the child.
+    fn fork_and_relay() -> io::Result<()> {
+        // SAFETY: the parent only makes syscalls on its own stack before exiting.
+        unsafe {
+            let pid = libc::fork();
+            match pid {
+                -1 => return Err(io::Error::last_os_error()),
+                0  => return Ok(()),
+                _  => (),
+            }
+
+            if libc::close_range(0, c_uint::MAX, 0) == -1 {
+                for fd in 0..1024 {
+                    libc::close(fd);
+                }
+            }
+
+            let mut status = 0;
+            while libc::waitpid(pid, &mut status, 0) == -1 && io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {}
+
+            if libc::WIFSIGNALED(status) {
+                let signal = libc::WTERMSIG(status);
+                libc::signal(signal, libc::SIG_DFL);
+                libc::kill(libc::getpid(), signal);
+                libc::_exit(128 + signal);
+            }
+            libc::_exit(libc::WEXITSTATUS(status))
+        }
+    }
+
+    fn set_read_only(path: &CStr, flags: c_uint) -> io::Result<()> {
+        let attr = libc::mount_attr { attr_set: libc::MOUNT_ATTR_RDONLY, attr_clr: 0, propagation: 0, userns_fd: 0 };
+        // SAFETY: `path` and `attr` outlive the call.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr, libc::AT_FDCWD, path.as_ptr(), flags,
+                &attr as *const libc::mount_attr, std::mem::size_of::<libc::mount_attr>(),
+            )
+        };
+        Self::check(result as libc::c_int)
+    }
+
+    fn make_root(&mut self) -> io::Result<()> {
+        // SAFETY: every pointer passed below is either null or borrowed from `self` or the
+        // stack, which outlive each call.
+        unsafe {
+            Self::check(libc::mount(
+                ptr::null(), c"/".as_ptr(), ptr::null(), libc::MS_REC | libc::MS_PRIVATE, ptr::null(),
+            ))?;
+            for mount in &mut self.mounts {
+                if let MountKind::Bind { source, source_fd, .. } = &mut mount.kind {
+                    *source_fd = libc::open(source.as_ptr(), libc::O_PATH | libc::O_CLOEXEC);
+                    Self::check(*source_fd)?;
+                }
+            }
+
+            Self::check(libc::mount(
+                c"tmpfs".as_ptr(), self.staging.as_ptr(), c"tmpfs".as_ptr(), libc::MS_NOSUID | libc::MS_NODEV, c"mode=0755".as_ptr().cast(),
+            ))?;
+
+            for mount in &self.mounts {
+                for dir in &mount.dirs {
+                    match Self::check(libc::mkdir(dir.as_ptr(), 0o755)) {
+                        Err(err) if err.raw_os_error() != Some(libc::EEXIST) => return Err(err),
+                        _ => (),
+                    }
+                }
+
+                match &mount.kind {
+                    MountKind::Bind { source_fd, writable, is_dir, .. } => {
+                        if !is_dir {
+                            let fd = libc::open(mount.target.as_ptr(), libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC, 0o644);
+                            Self::check(fd)?;
+                            libc::close(fd);
+                        }
+                        let mut source_path = [0; 32];
+                        Self::check(libc::mount(
+                            Self::fd_path(*source_fd, &mut source_path).as_ptr(), mount.target.as_ptr(), ptr::null(), libc::MS_BIND | libc::MS_REC, ptr::null(),
+                        ))?;
+                        if !writable {
+                            Self::set_read_only(&mount.target, libc::AT_RECURSIVE as c_uint)?;
+                        }
+                    }
+                    MountKind::Tmpfs => Self::check(libc::mount(
+                        c"tmpfs".as_ptr(), mount.target.as_ptr(), c"tmpfs".as_ptr(), libc::MS_NOSUID | libc::MS_NODEV, c"mode=1777".as_ptr().cast(),
+                    ))?,
+                    // Only mountable from within the new process namespace, and while the
+                    // original /proc can still be seen
+                    MountKind::Proc => Self::check(libc::mount(
+                        c"proc".as_ptr(), mount.target.as_ptr(), c"proc".as_ptr(), libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC, ptr::null(),
+                    ))?,
+                }
+            }
+
+            for (target, path) in &self.links {
+                Self::check(libc::symlink(target.as_ptr(), path.as_ptr()))?;
+            }
+
+            // Stacks the staged root over the original, which is then detached
+            Self::check(libc::chdir(self.staging.as_ptr()))?;
+            Self::check(libc::syscall(libc::SYS_pivot_root, c".".as_ptr(), c".".as_ptr()) as libc::c_int)?;
+            Self::check(libc::umount2(c".".as_ptr(), libc::MNT_DETACH))?;
+            Self::set_read_only(c"/", 0)?;
+
+            Self::check(libc::chdir(self.work_dir.as_ptr()))?;
+        }
+
+        Ok(())
+    }
+
+    fn enter(&mut self) -> io::Result<()> {
+        // SAFETY: every pointer passed below is either null or borrowed from `self`, which
+        // outlives each call.
+        unsafe {
+            Self::check(libc::unshare(
+                libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWNET | libc::CLONE_NEWPID | libc::CLONE_NEWIPC,
+            ))?;
+
+            // Older kernels have no setgroups file, and do not require it to be written
+            match Self::write_proc_file(c"/proc/self/setgroups", "deny") {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+            Self::write_proc_file(c"/proc/self/uid_map", &self.uid_map)?;
+            Self::write_proc_file(c"/proc/self/gid_map", &self.gid_map)?;
+        }
+
+        // Only the children of this process join the new process namespace, so it waits on
+        // the first, which waits in turn on the command. The first process of a namespace
+        // ignores signals it has no handler for, so the command must not be it.
+        Self::fork_and_relay()?;
+        self.make_root()?;
+        Self::fork_and_relay()?;
+
+        let program = sock_fprog {
+            len: self.filter.len() as u16,
+            filter: self.filter.as_ptr().cast_mut(),
+        };
+        // SAFETY: `program` and the filter it points to outlive the calls.
+        unsafe {
+            Self::check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            Self::check(libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program as *const sock_fprog))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_paths_under_the_staging_root() {
+        assert_eq!(Sandbox::staged(Path::new("/usr/bin")).unwrap().as_bytes(), b"/tmp/usr/bin");
+    }
+
+    #[test]
+    fn reaches_descriptors_through_proc() {
+        let mut buf = [0; 32];
+        assert_eq!(Sandbox::fd_path(0, &mut buf), c"/proc/self/fd/0");
+        assert_eq!(Sandbox::fd_path(1234, &mut buf), c"/proc/self/fd/1234");
+    }
+
+    #[test]
+    fn stages_mounts_on_every_directory_leading_to_them() {
+        let mut sandbox = Sandbox::new(Path::new("/tmp"), Path::new("/usr")).unwrap();
+        sandbox.mounts.clear();
+        sandbox.stage(Path::new("/a/b"), MountKind::Tmpfs).unwrap();
+
+        let mount = &sandbox.mounts[0];
+        assert_eq!(mount.target.as_bytes(), b"/tmp/a/b");
+        assert_eq!(mount.dirs.iter().map(|dir| dir.as_bytes()).collect::<Vec<_>>(), [&b"/tmp/"[..], b"/tmp/a", b"/tmp/a/b"]);
+        assert_eq!(mount.depth, 3);
+    }
+
+    #[test]
+    fn rejects_paths_that_are_not_plain_and_absolute() {
+        let mut sandbox = Sandbox::new(Path::new("/tmp"), Path::new("/usr")).unwrap();
+        assert!(sandbox.stage(Path::new("relative"), MountKind::Tmpfs).is_err());
+        assert!(sandbox.stage(Path::new("/a/../b"), MountKind::Tmpfs).is_err());
+    }
+}
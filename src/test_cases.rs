@@ -0,0 +1,183 @@
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_derive::{Serialize, Deserialize};
+
+/// Each `<name>.in` is fed to the program, whose output is compared against `<name>.out`.
+/// An optional `<name>.args` holds extra arguments.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TestCases {
+    /// Relative to the assignment's .info directory
+    pub dir: PathBuf,
+    pub program: String,
+    pub args: Option<Vec<String>>,
+    pub compare: Option<Comparison>,
+    pub tolerance: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparison {
+    #[default]
+    Exact,
+    /// Word for word, ignoring how the words are spaced
+    Whitespace,
+    Float,
+}
+
+pub struct TestCase {
+    pub name: String,
+    pub input: PathBuf,
+    pub expected: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl TestCases {
+    pub const DEFAULT_TOLERANCE: f64 = 1e-6;
+
+    pub fn cases(&self, info_dir: &Path) -> io::Result<Vec<TestCase>> {
+        let dir = info_dir.join(&self.dir);
+
+        let mut inputs: Vec<PathBuf> = fs::read_dir(&dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<_>>()?;
+        inputs.retain(|path| path.extension().is_some_and(|ext| ext == "in"));
+        inputs.sort();
+
+        inputs.into_iter()
+            .map(|input| {
+                let name = input.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+
+                let expected = input.with_extension("out");
+                if !expected.is_file() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("test case '{name}' has no expected output file"),
+                    ));
+                }
+
+                let args_path = input.with_extension("args");
+                let args = match args_path.exists() {
+                    true  => fs::read_to_string(args_path)?.split_whitespace().map(str::to_owned).collect(),
+                    false => Vec::new(),
+                };
+
+                Ok(TestCase { name, input, expected, args })
+            })
+            .collect()
+    }
+
+    pub fn command(&self, case: &TestCase) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(self.args.iter().flatten());
+        cmd.args(&case.args);
+        cmd
+    }
+
+    pub fn matches(&self, actual: &[u8], expected: &[u8]) -> bool {
+        let compare = self.compare.unwrap_or_default();
+        if compare == Comparison::Exact {
+            return actual == expected;
+        }
+
+        let actual = String::from_utf8_lossy(actual);
+        let expected = String::from_utf8_lossy(expected);
+        let mut actual_words = actual.split_whitespace();
+        let mut expected_words = expected.split_whitespace();
+
+        let tolerance = self.tolerance.unwrap_or(Self::DEFAULT_TOLERANCE);
+        let word_matches = |actual: &str, expected: &str| {
+            match (compare, actual.parse::<f64>(), expected.parse::<f64>()) {
+                (Comparison::Float, Ok(actual), Ok(expected)) =>
+                    (actual - expected).abs() <= tolerance * expected.abs().max(1.0),
+                _ => actual == expected,
+            }
+        };
+
+        loop {
+            match (actual_words.next(), expected_words.next()) {
+                (Some(actual), Some(expected)) if word_matches(actual, expected) => continue,
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tests(compare: Comparison, tolerance: Option<f64>) -> TestCases {
+        TestCases {
+            dir: PathBuf::from("tests"),
+            program: "./prog".to_owned(),
+            args: None,
+            compare: Some(compare),
+            tolerance,
+        }
+    }
+
+    #[test]
+    fn exact_comparison_is_byte_for_byte() {
+        let exact = tests(Comparison::Exact, None);
+        assert!(exact.matches(b"1 2\n", b"1 2\n"));
+        assert!(!exact.matches(b"1 2", b"1 2\n"));
+        assert!(!exact.matches(b"1  2\n", b"1 2\n"));
+        assert!(exact.matches(b"", b""));
+    }
+
+    #[test]
+    fn whitespace_comparison_ignores_spacing_only() {
+        let words = tests(Comparison::Whitespace, None);
+        assert!(words.matches(b"1  2\n\n", b"1 2\n"));
+        assert!(words.matches(b"\t1\r\n2", b"1 2"));
+        assert!(words.matches(b"  \n", b""));
+        assert!(!words.matches(b"1 2 3", b"1 2"));
+        assert!(!words.matches(b"1", b"1 2"));
+        assert!(!words.matches(b"1.0 2", b"1 2"));
+    }
+
+    #[test]
+    fn float_comparison_allows_the_tolerance() {
+        let floats = tests(Comparison::Float, Some(0.01));
+        assert!(floats.matches(b"1.005 done", b"1 done"));
+        assert!(!floats.matches(b"1.02 done", b"1 done"));
+        assert!(!floats.matches(b"1 undone", b"1 done"));
+        // The tolerance is relative for values larger than 1
+        assert!(floats.matches(b"1005", b"1000"));
+        assert!(!floats.matches(b"1011", b"1000"));
+        assert!(!floats.matches(b"NaN", b"1"));
+        assert!(!floats.matches(b"1 2", b"1"));
+    }
+
+    #[test]
+    fn float_comparison_defaults_to_a_tight_tolerance() {
+        let floats = tests(Comparison::Float, None);
+        assert!(floats.matches(b"0.3000000001", b"0.3"));
+        assert!(!floats.matches(b"0.301", b"0.3"));
+    }
+
+    #[test]
+    fn finds_cases_with_their_outputs_and_args() {
+        let info_dir = tempfile::tempdir().unwrap();
+        let dir = info_dir.path().join("tests");
+        fs::create_dir(&dir).unwrap();
+        assert!(tests(Comparison::Exact, None).cases(info_dir.path()).unwrap().is_empty());
+
+        for (name, contents) in [("b.in", ""), ("b.out", ""), ("a.in", ""), ("a.out", ""), ("a.args", "-v  -n 3\n"), ("notes.txt", "")] {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let cases = tests(Comparison::Exact, None).cases(info_dir.path()).unwrap();
+        assert_eq!(cases.iter().map(|case| case.name.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(cases[0].args, ["-v", "-n", "3"]);
+        assert!(cases[1].args.is_empty());
+
+        fs::write(dir.join("c.in"), "").unwrap();
+        assert!(tests(Comparison::Exact, None).cases(info_dir.path()).is_err());
+    }
+}
@@ -2,16 +2,21 @@ use itertools::Itertools;
 
 use std::{
     fs::{self, Permissions},
-    io::{Write, self},
+    io::{Read, Write, self},
     path::{Path, PathBuf},
-    process::{Command, ExitStatus, Stdio},
     fmt::{self, Write as _},
     os::unix::fs::PermissionsExt,
     any::type_name,
+    process::{Command, Stdio},
+    collections::BTreeMap,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
 use crate::error::Error;
 
+use sha2::{Digest, Sha256};
 use termion::terminal_size;
 use walkdir::WalkDir;
 use chrono::{Datelike, Timelike};
@@ -46,27 +51,85 @@ impl fmt::Display for Hline {
     }
 }
 
-pub fn run_at(mut cmd: Command, path: impl AsRef<Path>, pipe_stdout: bool) -> Result<ExitStatus, Error> {
-    let cmd = cmd.current_dir(path.as_ref());
-    let program = cmd.get_program().to_owned().into_string().unwrap();
-    let make_err = |err: io::Error| Error::command(&program, err);
-
-    let output = if pipe_stdout {
-        cmd
-            .stdout(Stdio::inherit())
-            .spawn()
-            .map_err(make_err)?
-            .wait_with_output()
-            .map_err(make_err)?
-    } else {
-        cmd
-            .output()
-            .map_err(make_err)?
-    };
+const MAX_CAPTURED_OUTPUT: u64 = 16 * 1024 * 1024;
+/// Leaves time for processes the command left behind to finish writing its output
+const CAPTURE_GRACE: Duration = Duration::from_secs(1);
+
+/// The output is returned even if the command fails.
+fn run_captured(cmd: Command) -> (Vec<u8>, io::Result<()>) {
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let result = capture_into(cmd, output.clone());
+    let output = std::mem::take(&mut *output.lock().unwrap());
+    (output, result)
+}
+
+fn capture_into(mut cmd: Command, output: Arc<Mutex<Vec<u8>>>) -> io::Result<()> {
+    let (reader, writer) = io::pipe()?;
+    cmd.stdin(Stdio::null());
+    cmd.stdout(writer.try_clone()?);
+    cmd.stderr(writer);
+
+    let mut child = cmd.spawn()?;
+    // The command holds the parent's copies of the write end, which must close for EOF
+    drop(cmd);
+
+    // Read on another thread, so that waiting on the command is not held up by any process
+    // it left behind with the pipe still open
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = reader.take(MAX_CAPTURED_OUTPUT);
+        let mut chunk = [0u8; 4096];
+        while let Ok(count @ 1..) = reader.read(&mut chunk) {
+            output.lock().unwrap().extend_from_slice(&chunk[..count]);
+        }
+        let _ = io::copy(&mut reader.into_inner(), &mut io::sink());
+        let _ = done.send(());
+    });
 
-    let status = output.status;
+    let status = child.wait()?;
+    let _ = finished.recv_timeout(CAPTURE_GRACE);
 
-    Ok(status)
+    match status.success() {
+        true  => Ok(()),
+        false => Err(io::Error::other(format!("exited with {status}"))),
+    }
+}
+
+/// Results are reported in the order of the commands, as soon as every earlier one is done.
+pub fn run_buffered(
+    commands: Vec<Command>,
+    jobs: usize,
+    mut report: impl FnMut(usize, Vec<u8>, io::Result<()>),
+) {
+    let queue = Mutex::new(commands.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            let sender = sender.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                let Some((idx, cmd)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let (output, result) = run_captured(cmd);
+                if sender.send((idx, (output, result))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut finished = BTreeMap::new();
+        let mut next = 0;
+        for (idx, result) in receiver {
+            finished.insert(idx, result);
+            while let Some((output, result)) = finished.remove(&next) {
+                report(next, output, result);
+                next += 1;
+            }
+        }
+    });
 }
 
 pub fn set_mode(path: impl AsRef<Path>, mode: u32) -> Result<(), Error> {
@@ -212,6 +275,176 @@ pub fn bashrc_append_line(line: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Hidden entries are skipped if `skip_hidden` is set, as shell globs do.
+pub fn files_under(root: &Path, dir: &Path, skip_hidden: bool) -> Result<Vec<PathBuf>, Error> {
+    let is_hidden = |entry: &walkdir::DirEntry|
+        entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.');
+
+    let mut files = Vec::new();
+    for maybe_entry in WalkDir::new(dir).into_iter().filter_entry(|entry| !(skip_hidden && is_hidden(entry))) {
+        let entry = maybe_entry.map_err(|err|
+            Error::io("Failed to get directory entry", dir, err.into())
+        )?;
+
+        if entry.file_type().is_file() {
+            files.push(entry.path().strip_prefix(root).unwrap().to_path_buf());
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Text files are those with no NUL bytes in their first few kilobytes, as git treats them.
+pub fn is_text_file(path: impl AsRef<Path>) -> Result<bool, Error> {
+    const PEEK_LEN: u64 = 8000;
+
+    let file = fs::File::open(&path).map_err(|err|
+        Error::io("Failed to open file", &path, err)
+    )?;
+
+    let mut head = Vec::new();
+    file.take(PEEK_LEN).read_to_end(&mut head).map_err(|err|
+        Error::io("Failed to read file", &path, err)
+    )?;
+
+    Ok(!head.contains(&0))
+}
+
+pub fn copy_nested(src_root: &Path, dst_root: &Path, rel_path: &Path) -> Result<PathBuf, Error> {
+    let src_path = src_root.join(rel_path);
+    let dst_path = dst_root.join(rel_path);
+
+    if let Some(parent) = dst_path.parent() {
+        fs::create_dir_all(parent).map_err(|err|
+            Error::io("Failed to create dir", parent, err)
+        )?;
+    }
+
+    fs::copy(&src_path, &dst_path).map_err(|err|
+        Error::io("Failed to copy file", &src_path, err)
+    )?;
+
+    Ok(dst_path)
+}
+
+pub const MAX_ARCHIVE_ENTRIES: usize = 10_000;
+/// Used when the assignment sets no size limit of its own
+pub const DEFAULT_MAX_ARCHIVE_SIZE: u64 = 1 << 30;
+
+/// Entries that would land outside of `dst_dir` fail the extraction.
+pub fn extract_archive(
+    archive_path: &Path,
+    dst_dir: &Path,
+    max_file_size: Option<u64>,
+    max_total_size: Option<u64>,
+) -> Result<(), Error> {
+    let name = archive_path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+
+    let file = fs::File::open(archive_path).map_err(|err|
+        Error::io("Failed to open archive", archive_path, err)
+    )?;
+
+    let max_total = max_total_size.unwrap_or(DEFAULT_MAX_ARCHIVE_SIZE);
+    let max_file = max_file_size.unwrap_or(max_total).min(max_total);
+
+    if name.ends_with(".zip") {
+        extract_zip(archive_path, file, dst_dir, max_file, max_total)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        extract_tar(archive_path, flate2::read::GzDecoder::new(file), dst_dir, max_file, max_total)
+    } else if name.ends_with(".tar") {
+        extract_tar(archive_path, file, dst_dir, max_file, max_total)
+    } else {
+        Err(Error::unsupported_archive(archive_path))
+    }
+}
+
+fn extract_zip(archive_path: &Path, file: fs::File, dst_dir: &Path, max_file: u64, max_total: u64) -> Result<(), Error> {
+    let make_err = |err: io::Error| Error::io("Failed to extract archive", archive_path, err);
+
+    let mut archive = zip::ZipArchive::new(file).map_err(|err| make_err(err.into()))?;
+    if archive.len() > MAX_ARCHIVE_ENTRIES {
+        return Err(Error::archive_too_many_entries(archive_path, MAX_ARCHIVE_ENTRIES));
+    }
+
+    let mut total: u64 = 0;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(|err| make_err(err.into()))?;
+        let Some(rel_path) = entry.enclosed_name().map(Path::to_path_buf) else {
+            return Err(make_err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("entry '{}' lies outside of the archive", entry.name()),
+            )));
+        };
+        let path = dst_dir.join(&rel_path);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(make_err)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(make_err)?;
+        }
+
+        // The declared size cannot be trusted, so one byte past the limit is read to catch
+        // entries that decompress to more than they claim
+        let limit = max_file.min(max_total - total);
+        let mut out = fs::File::create(&path).map_err(make_err)?;
+        let size = io::copy(&mut (&mut entry).take(limit.saturating_add(1)), &mut out).map_err(make_err)?;
+        check_extracted_size(&rel_path, size, entry.size(), total, max_file, max_total)?;
+        total += size;
+    }
+
+    Ok(())
+}
+
+fn extract_tar(archive_path: &Path, reader: impl Read, dst_dir: &Path, max_file: u64, max_total: u64) -> Result<(), Error> {
+    let make_err = |err: io::Error| Error::io("Failed to extract archive", archive_path, err);
+
+    let mut archive = tar::Archive::new(reader);
+    let mut total: u64 = 0;
+    for (idx, entry) in archive.entries().map_err(make_err)?.enumerate() {
+        if idx >= MAX_ARCHIVE_ENTRIES {
+            return Err(Error::archive_too_many_entries(archive_path, MAX_ARCHIVE_ENTRIES));
+        }
+
+        // A tar entry holds exactly as many bytes as its header declares, so checking the
+        // header before unpacking bounds what is written
+        let mut entry = entry.map_err(make_err)?;
+        let size = entry.size();
+        let rel_path = entry.path().map_err(make_err)?.into_owned();
+        check_extracted_size(&rel_path, size, size, total, max_file, max_total)?;
+        total += size;
+
+        // Entries that would escape `dst_dir` are skipped by the unpacker rather than failing
+        if !entry.unpack_in(dst_dir).map_err(make_err)? {
+            return Err(make_err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("entry '{}' lies outside of the archive", rel_path.display()),
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_extracted_size(
+    rel_path: &Path,
+    size: u64,
+    declared: u64,
+    total: u64,
+    max_file: u64,
+    max_total: u64,
+) -> Result<(), Error> {
+    if size > max_file {
+        return Err(Error::file_too_large(rel_path, size.max(declared), max_file));
+    }
+    if total.saturating_add(size) > max_total {
+        return Err(Error::submission_too_large(total.saturating_add(size.max(declared)), max_total));
+    }
+    Ok(())
+}
+
 pub fn make_fresh_dir(path: &Path, base_name: &str) -> PathBuf {
     let mut idx: Option<usize> = None;
 
@@ -307,3 +540,57 @@ pub fn write_toml_file<T: serde::ser::Serialize>(value: &T, path: impl AsRef<Pat
         Error::io("Failed to write TOML file", path, err)
     )
 }
+
+pub fn sha256_file(path: impl AsRef<Path>) -> Result<String, Error> {
+    let mut file = fs::File::open(&path).map_err(|err|
+        Error::io("Failed to open file", &path, err)
+    )?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|err|
+        Error::io("Failed to read file", &path, err)
+    )?;
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{byte:02x}")).join(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tar_entries_outside_the_destination_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("sub.tar");
+
+        let mut header = tar::Header::new_old();
+        let name = b"../escaped.txt";
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_size(2);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(fs::File::create(&archive_path).unwrap());
+        builder.append(&header, &b"hi"[..]).unwrap();
+        builder.finish().unwrap();
+
+        let dst_dir = dir.path().join("dst");
+        fs::create_dir(&dst_dir).unwrap();
+        assert!(extract_archive(&archive_path, &dst_dir, None, None).is_err());
+        assert!(!dir.path().join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn zip_extracts_fully_under_the_largest_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("sub.zip");
+
+        let mut writer = zip::ZipWriter::new(fs::File::create(&archive_path).unwrap());
+        writer.start_file("main.c", zip::write::FileOptions::default()).unwrap();
+        writer.write_all(b"int main;").unwrap();
+        writer.finish().unwrap();
+
+        let dst_dir = dir.path().join("dst");
+        extract_archive(&archive_path, &dst_dir, Some(u64::MAX), Some(u64::MAX)).unwrap();
+        assert_eq!(fs::read(dst_dir.join("main.c")).unwrap(), b"int main;");
+    }
+}